@@ -0,0 +1,73 @@
+//! Times the same programs in the evaluator and in the virtual machine
+//!
+//! Run with `cargo bench`.  Each program is run a few times in each and the fastest
+//! run is reported, parsing and compiling included
+use std::time::{Duration, Instant};
+
+use monkey::{compiler, lexer, parser, vm, Interpreter, Object};
+
+const RUNS: usize = 5;
+
+const PROGRAMS: [(&str, &str); 3] = [
+    (
+        "fib",
+        "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; fib(25)",
+    ),
+    (
+        "locals",
+        "let sum = fn(n, total) { let next = total + n; if (n == 0) { next } else { sum(n - 1, next) } };
+         let repeat = fn(times, total) { if (times == 0) { total } else { repeat(times - 1, sum(1000, total)) } };
+         repeat(200, 0)",
+    ),
+    (
+        "closures",
+        "let adder = fn(a) { fn(b) { a + b } };
+         let count = fn(n, total) { if (n == 0) { total } else { count(n - 1, adder(n)(total)) } };
+         let repeat = fn(times, total) { if (times == 0) { total } else { repeat(times - 1, count(1000, total)) } };
+         repeat(100, 0)",
+    ),
+];
+
+fn evaluator(source: &str) -> Object {
+    Interpreter::new().eval_str(source).unwrap()
+}
+
+fn virtual_machine(source: &str) -> Object {
+    let mut parser = parser::Parser::new(lexer::Lexer::new(source));
+    let program = parser.parse_program();
+    assert!(parser.errors.is_empty(), "{:?}", parser.errors);
+    let bytecode = compiler::compile(&program).unwrap();
+    vm::Vm::new(bytecode).run()
+}
+
+/// The fastest of `RUNS` runs, and the value the program gave
+fn time(run: fn(&str) -> Object, source: &str) -> (Duration, Object) {
+    let mut fastest = Duration::MAX;
+    let mut value = Object::Null;
+    for _ in 0..RUNS {
+        let start = Instant::now();
+        value = run(source);
+        fastest = fastest.min(start.elapsed());
+    }
+    (fastest, value)
+}
+
+fn main() {
+    println!(
+        "{:<10} {:>12} {:>12} {:>8}",
+        "program", "evaluator", "vm", "speedup"
+    );
+    for (name, source) in PROGRAMS {
+        let (evaluated, expected) = time(evaluator, source);
+        let (executed, value) = time(virtual_machine, source);
+        assert_eq!(expected, value, "{} gave different values", name);
+
+        println!(
+            "{:<10} {:>12.2?} {:>12.2?} {:>7.2}x",
+            name,
+            evaluated,
+            executed,
+            evaluated.as_secs_f64() / executed.as_secs_f64()
+        );
+    }
+}
@@ -4,15 +4,202 @@ use crate::token;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Statement {
-    Let(Identifier, Expression),
-    Return(Expression),
+    /// The token is the `let` keyword
+    Let(token::Token, Identifier, Expression),
+    /// The token is the `return` keyword
+    Return(token::Token, Expression),
     Expression(token::Token, Expression),
 }
 
+impl Statement {
+    /// The source covered by the statement, from its first to its last token
+    #[must_use]
+    pub fn span(&self) -> token::Span {
+        match self {
+            Self::Let(token, _, expression)
+            | Self::Return(token, expression)
+            | Self::Expression(token, expression) => token.span.to(expression.span()),
+        }
+    }
+}
+
+impl fmt::Display for Statement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Let(_, ident, expression) => write!(f, "let {} = {};", ident.value, expression),
+            Self::Return(_, expression) => write!(f, "return {};", expression),
+            Self::Expression(_, expression) => write!(f, "{}", expression),
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Expression {
     Identifier(token::Token),
     Integer(token::Token),
+    Float(token::Token),
+    Boolean(token::Token),
+    /// The token's literal holds the contents with escapes already resolved
+    StringLiteral(token::Token),
+    /// A prefix operator applied to a single operand, e.g. `!ok` or `-5`.  The
+    /// token is the operator
+    Prefix {
+        token: token::Token,
+        right: Box<Expression>,
+    },
+    /// A binary operator between two operands, e.g. `5 + 5`.  The token is the
+    /// operator
+    Infix {
+        token: token::Token,
+        left: Box<Expression>,
+        right: Box<Expression>,
+    },
+    /// `if (condition) { ... } else { ... }`.  The token is the `if` keyword, with
+    /// its span stretched to the last closing brace
+    If {
+        token: token::Token,
+        condition: Box<Expression>,
+        consequence: BlockStatement,
+        alternative: Option<BlockStatement>,
+    },
+    /// `fn(x, y) { ... }`.  The token is the `fn` keyword, with its span stretched to
+    /// the closing brace
+    FunctionLiteral {
+        token: token::Token,
+        params: Vec<Identifier>,
+        body: BlockStatement,
+    },
+    /// A call such as `add(1, 2)`.  The token is the opening parenthesis, with its
+    /// span stretched to the closing one
+    Call {
+        token: token::Token,
+        function: Box<Expression>,
+        args: Vec<Expression>,
+    },
+    /// `[1, 2, 3]`.  The token is the opening bracket, with its span stretched to
+    /// the closing one
+    ArrayLiteral {
+        token: token::Token,
+        elements: Vec<Expression>,
+    },
+    /// `{"name": "monkey", 1: true}`.  The token is the opening brace, with its span
+    /// stretched to the closing one.  Pairs keep their source order
+    HashLiteral {
+        token: token::Token,
+        pairs: Vec<(Expression, Expression)>,
+    },
+    /// `left[index]`.  The token is the opening bracket, with its span stretched to
+    /// the closing one
+    Index {
+        token: token::Token,
+        left: Box<Expression>,
+        index: Box<Expression>,
+    },
+}
+
+impl Expression {
+    /// The token the expression was built around, e.g. the operator of an infix
+    /// expression
+    #[must_use]
+    pub const fn token(&self) -> &token::Token {
+        match self {
+            Self::Identifier(token)
+            | Self::Integer(token)
+            | Self::Float(token)
+            | Self::Boolean(token)
+            | Self::StringLiteral(token)
+            | Self::Prefix { token, .. }
+            | Self::Infix { token, .. }
+            | Self::If { token, .. }
+            | Self::FunctionLiteral { token, .. }
+            | Self::Call { token, .. }
+            | Self::ArrayLiteral { token, .. }
+            | Self::HashLiteral { token, .. }
+            | Self::Index { token, .. } => token,
+        }
+    }
+
+    /// The source covered by the expression, including its operands
+    #[must_use]
+    pub fn span(&self) -> token::Span {
+        match self {
+            Self::Prefix { token, right } => token.span.to(right.span()),
+            Self::Infix { left, right, .. } => left.span().to(right.span()),
+            Self::Call {
+                token, function, ..
+            } => function.span().to(token.span),
+            Self::Index { token, left, .. } => left.span().to(token.span),
+            _ => self.token().span,
+        }
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Identifier(token)
+            | Self::Integer(token)
+            | Self::Float(token)
+            | Self::Boolean(token) => {
+                write!(f, "{}", token.literal())
+            }
+            // Debug quotes the string and escapes it again much as Monkey would
+            Self::StringLiteral(token) => write!(f, "{:?}", token.literal()),
+            Self::Prefix { token, right } => write!(f, "({}{})", token.literal(), right),
+            Self::Infix { token, left, right } => {
+                write!(f, "({} {} {})", left, token.literal(), right)
+            }
+            Self::If {
+                condition,
+                consequence,
+                alternative,
+                ..
+            } => {
+                write!(f, "if {} {}", condition, consequence)?;
+                if let Some(alternative) = alternative {
+                    write!(f, " else {}", alternative)?;
+                }
+                Ok(())
+            }
+            Self::FunctionLiteral { params, body, .. } => {
+                let params: Vec<&str> = params.iter().map(|param| param.value.as_str()).collect();
+                write!(f, "fn({}) {}", params.join(", "), body)
+            }
+            Self::Call { function, args, .. } => {
+                let args: Vec<String> = args.iter().map(ToString::to_string).collect();
+                write!(f, "{}({})", function, args.join(", "))
+            }
+            Self::ArrayLiteral { elements, .. } => {
+                let elements: Vec<String> = elements.iter().map(ToString::to_string).collect();
+                write!(f, "[{}]", elements.join(", "))
+            }
+            Self::HashLiteral { pairs, .. } => {
+                let pairs: Vec<String> = pairs
+                    .iter()
+                    .map(|(key, value)| format!("{}: {}", key, value))
+                    .collect();
+                write!(f, "{{{}}}", pairs.join(", "))
+            }
+            Self::Index { left, index, .. } => write!(f, "({}[{}])", left, index),
+        }
+    }
+}
+
+/// A brace-delimited sequence of statements.  The token is the opening brace
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockStatement {
+    pub token: token::Token,
+    pub statements: Vec<Statement>,
+}
+
+impl fmt::Display for BlockStatement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.statements.is_empty() {
+            return write!(f, "{{}}");
+        }
+        let statements: Vec<String> = self.statements.iter().map(ToString::to_string).collect();
+        write!(f, "{{ {} }}", statements.join(" "))
+    }
 }
 
 #[derive(Default, Debug)]
@@ -22,9 +209,9 @@ pub struct Program {
 
 impl fmt::Display for Program {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "PROGRAM: \n")?;
+        writeln!(f, "PROGRAM: ")?;
         for (idx, statement) in self.statements.iter().enumerate() {
-            write!(f, "--- {}: {:?}\n", idx, statement)?;
+            writeln!(f, "--- {}: {}", idx, statement)?;
         }
         Ok(())
     }
@@ -0,0 +1,167 @@
+use core::fmt;
+use std::rc::Rc;
+
+use crate::object::Object;
+
+/// The Rust side of a builtin.  It returns the message of a runtime error on
+/// failure, which the evaluator turns into an error at the call site
+pub type BuiltinFn = dyn Fn(&[Object]) -> Result<Object, String>;
+
+/// A function implemented in Rust, either one of the builtins below or one a host
+/// program registered with the interpreter
+#[derive(Clone)]
+pub struct Builtin {
+    pub name: Rc<str>,
+    /// The number of arguments the function takes, or `None` if it takes any number
+    pub arity: Option<usize>,
+    pub function: Rc<BuiltinFn>,
+}
+
+impl Builtin {
+    #[must_use]
+    pub fn new(
+        name: &str,
+        arity: Option<usize>,
+        function: impl Fn(&[Object]) -> Result<Object, String> + 'static,
+    ) -> Self {
+        Self {
+            name: Rc::from(name),
+            arity,
+            function: Rc::new(function),
+        }
+    }
+}
+
+// Closures cannot be compared, so two builtins are equal only when they share
+// the same function.  A host function named like a builtin is still a different one
+impl PartialEq for Builtin {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name && Rc::ptr_eq(&self.function, &other.function)
+    }
+}
+
+impl Eq for Builtin {}
+
+impl fmt::Debug for Builtin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Builtin")
+            .field("name", &self.name)
+            .field("arity", &self.arity)
+            .finish_non_exhaustive()
+    }
+}
+
+impl fmt::Display for Builtin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "builtin function {}", self.name)
+    }
+}
+
+/// Name, arity and implementation of every builtin
+type BuiltinEntry = (
+    &'static str,
+    Option<usize>,
+    fn(&[Object]) -> Result<Object, String>,
+);
+
+const BUILTINS: &[BuiltinEntry] = &[
+    ("len", Some(1), len),
+    ("first", Some(1), first),
+    ("last", Some(1), last),
+    ("rest", Some(1), rest),
+    ("push", Some(2), push),
+    ("puts", None, puts),
+];
+
+/// Finds the builtin called `name`.  Consulted only once the environment has no
+/// binding for `name`, so a `let` can shadow a builtin
+#[must_use]
+pub fn lookup(name: &str) -> Option<Object> {
+    names().position(|builtin| builtin == name).and_then(get)
+}
+
+/// The names of every builtin.  The compiler refers to a builtin by its position in
+/// this list
+pub fn names() -> impl Iterator<Item = &'static str> {
+    BUILTINS.iter().map(|(name, _, _)| *name)
+}
+
+thread_local! {
+    /// Every builtin, made once per thread so that each lookup of one shares its
+    /// function and compares equal to the others
+    static MADE: Vec<Builtin> = BUILTINS
+        .iter()
+        .map(|(name, arity, function)| Builtin::new(name, *arity, function))
+        .collect();
+}
+
+/// The builtin at `index` in `names`
+#[must_use]
+pub fn get(index: usize) -> Option<Object> {
+    MADE.with(|builtins| builtins.get(index).cloned().map(Object::Builtin))
+}
+
+fn len(args: &[Object]) -> Result<Object, String> {
+    let len = match &args[0] {
+        Object::String(value) => value.chars().count(),
+        Object::Array(elements) => elements.len(),
+        Object::Hash(pairs) => pairs.len(),
+        other => {
+            return Err(format!(
+                "argument to `len` not supported, got {}",
+                other.type_name()
+            ))
+        }
+    };
+
+    i64::try_from(len)
+        .map(Object::Integer)
+        .map_err(|err| err.to_string())
+}
+
+fn first(args: &[Object]) -> Result<Object, String> {
+    let elements = array_argument("first", &args[0])?;
+    Ok(elements.first().cloned().unwrap_or(Object::Null))
+}
+
+fn last(args: &[Object]) -> Result<Object, String> {
+    let elements = array_argument("last", &args[0])?;
+    Ok(elements.last().cloned().unwrap_or(Object::Null))
+}
+
+/// A new array holding everything but the first element, or `null` for an empty
+/// array
+fn rest(args: &[Object]) -> Result<Object, String> {
+    let elements = array_argument("rest", &args[0])?;
+
+    match elements.split_first() {
+        Some((_, rest)) => Ok(Object::Array(rest.to_vec())),
+        None => Ok(Object::Null),
+    }
+}
+
+/// A new array with the element appended.  Arrays are values, so the original is
+/// left as it was
+fn push(args: &[Object]) -> Result<Object, String> {
+    let mut elements = array_argument("push", &args[0])?.to_vec();
+    elements.push(args[1].clone());
+    Ok(Object::Array(elements))
+}
+
+fn puts(args: &[Object]) -> Result<Object, String> {
+    for arg in args {
+        println!("{}", arg);
+    }
+    Ok(Object::Null)
+}
+
+fn array_argument<'a>(name: &str, arg: &'a Object) -> Result<&'a [Object], String> {
+    match arg {
+        Object::Array(elements) => Ok(elements),
+        other => Err(format!(
+            "argument to `{}` must be ARRAY, got {}",
+            name,
+            other.type_name()
+        )),
+    }
+}
@@ -0,0 +1,388 @@
+use core::fmt;
+use std::fmt::Write;
+
+/// A sequence of encoded instructions.  Each is a one byte opcode followed by its
+/// operands, big-endian, in the widths given by the opcode's definition
+pub type Instructions = Vec<u8>;
+
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Opcode {
+    /// Pushes the constant at the operand's index in the constants pool
+    Constant,
+    Pop,
+
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Equal,
+    NotEqual,
+    GreaterThan,
+    LessThan,
+    Minus,
+    Bang,
+
+    True,
+    False,
+    Null,
+
+    /// Jumps to the operand's offset if the popped value is not truthy
+    JumpNotTruthy,
+    Jump,
+
+    GetGlobal,
+    SetGlobal,
+    GetLocal,
+    SetLocal,
+    GetBuiltin,
+    /// Pushes the local of an enclosing function at the operand's index in the
+    /// running closure's free variables
+    GetFree,
+    /// Pushes the local in the operand's slot when it is kept in a cell, as a
+    /// closure captures it or it may not be set yet
+    GetCell,
+    SetCell,
+
+    /// Builds an array from the operand's number of values on the stack
+    Array,
+    /// Builds a hash from the operand's number of keys and values on the stack,
+    /// which alternate starting with a key
+    Hash,
+    Index,
+
+    /// Calls the function below the operand's number of arguments on the stack
+    Call,
+    /// Returns the popped value from the running function
+    ReturnValue,
+    /// Returns `null` from the running function
+    Return,
+    /// Wraps the function constant at the operand's index in a closure, capturing
+    /// the cells it refers to from the running call
+    Closure,
+}
+
+/// How an opcode is written out
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Definition {
+    pub name: &'static str,
+    /// The width in bytes of each operand
+    pub operand_widths: &'static [usize],
+}
+
+impl Opcode {
+    /// Every opcode, in the order of their byte values
+    pub const ALL: [Self; 32] = [
+        Self::Constant,
+        Self::Pop,
+        Self::Add,
+        Self::Sub,
+        Self::Mul,
+        Self::Div,
+        Self::Equal,
+        Self::NotEqual,
+        Self::GreaterThan,
+        Self::LessThan,
+        Self::Minus,
+        Self::Bang,
+        Self::True,
+        Self::False,
+        Self::Null,
+        Self::JumpNotTruthy,
+        Self::Jump,
+        Self::GetGlobal,
+        Self::SetGlobal,
+        Self::GetLocal,
+        Self::SetLocal,
+        Self::GetBuiltin,
+        Self::GetFree,
+        Self::GetCell,
+        Self::SetCell,
+        Self::Array,
+        Self::Hash,
+        Self::Index,
+        Self::Call,
+        Self::ReturnValue,
+        Self::Return,
+        Self::Closure,
+    ];
+
+    #[must_use]
+    pub const fn definition(self) -> Definition {
+        let (name, operand_widths): (&'static str, &'static [usize]) = match self {
+            Self::Constant => ("OpConstant", &[2]),
+            Self::Pop => ("OpPop", &[]),
+            Self::Add => ("OpAdd", &[]),
+            Self::Sub => ("OpSub", &[]),
+            Self::Mul => ("OpMul", &[]),
+            Self::Div => ("OpDiv", &[]),
+            Self::Equal => ("OpEqual", &[]),
+            Self::NotEqual => ("OpNotEqual", &[]),
+            Self::GreaterThan => ("OpGreaterThan", &[]),
+            Self::LessThan => ("OpLessThan", &[]),
+            Self::Minus => ("OpMinus", &[]),
+            Self::Bang => ("OpBang", &[]),
+            Self::True => ("OpTrue", &[]),
+            Self::False => ("OpFalse", &[]),
+            Self::Null => ("OpNull", &[]),
+            Self::JumpNotTruthy => ("OpJumpNotTruthy", &[2]),
+            Self::Jump => ("OpJump", &[2]),
+            Self::GetGlobal => ("OpGetGlobal", &[2]),
+            Self::SetGlobal => ("OpSetGlobal", &[2]),
+            Self::GetLocal => ("OpGetLocal", &[1]),
+            Self::SetLocal => ("OpSetLocal", &[1]),
+            Self::GetBuiltin => ("OpGetBuiltin", &[1]),
+            Self::GetFree => ("OpGetFree", &[1]),
+            Self::GetCell => ("OpGetCell", &[1]),
+            Self::SetCell => ("OpSetCell", &[1]),
+            Self::Array => ("OpArray", &[2]),
+            Self::Hash => ("OpHash", &[2]),
+            Self::Index => ("OpIndex", &[]),
+            Self::Call => ("OpCall", &[1]),
+            Self::ReturnValue => ("OpReturnValue", &[]),
+            Self::Return => ("OpReturn", &[]),
+            Self::Closure => ("OpClosure", &[2]),
+        };
+
+        Definition {
+            name,
+            operand_widths,
+        }
+    }
+
+    /// The number of bytes the opcode and its operands take up
+    #[must_use]
+    pub fn width(self) -> usize {
+        1 + self.definition().operand_widths.iter().sum::<usize>()
+    }
+}
+
+impl TryFrom<u8> for Opcode {
+    type Error = u8;
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        Self::ALL.get(usize::from(byte)).copied().ok_or(byte)
+    }
+}
+
+impl fmt::Display for Opcode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.definition().name)
+    }
+}
+
+/// Encodes one instruction.  Returns `None` if an operand does not fit in its width
+#[must_use]
+pub fn make(op: Opcode, operands: &[usize]) -> Option<Instructions> {
+    let definition = op.definition();
+    let mut instruction = Vec::with_capacity(op.width());
+    instruction.push(op as u8);
+
+    for (operand, width) in operands.iter().zip(definition.operand_widths) {
+        match width {
+            1 => instruction.push(u8::try_from(*operand).ok()?),
+            2 => instruction.extend_from_slice(&u16::try_from(*operand).ok()?.to_be_bytes()),
+            _ => unreachable!("no opcode has {} byte operands", width),
+        }
+    }
+
+    Some(instruction)
+}
+
+/// Decodes the operands that follow an opcode.  `instructions` starts just after the
+/// opcode and must hold every operand
+#[must_use]
+pub fn read_operands(definition: Definition, instructions: &[u8]) -> Vec<usize> {
+    let mut offset = 0;
+
+    definition
+        .operand_widths
+        .iter()
+        .map(|width| {
+            let operand = match width {
+                1 => usize::from(instructions[offset]),
+                2 => read_u16(&instructions[offset..]),
+                _ => unreachable!("no opcode has {} byte operands", width),
+            };
+            offset += width;
+            operand
+        })
+        .collect()
+}
+
+#[must_use]
+pub fn read_u16(instructions: &[u8]) -> usize {
+    usize::from(u16::from_be_bytes([instructions[0], instructions[1]]))
+}
+
+/// One instruction decoded from a sequence
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Instruction {
+    pub offset: usize,
+    pub op: Opcode,
+    pub operands: Vec<usize>,
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04} {}", self.offset, self.op)?;
+        for operand in &self.operands {
+            write!(f, " {}", operand)?;
+        }
+        Ok(())
+    }
+}
+
+/// Decodes `instructions` in order.  Decoding stops at the first byte that does not
+/// start a complete instruction, which is yielded as an error holding its offset
+pub fn decode(instructions: &[u8]) -> impl Iterator<Item = Result<Instruction, usize>> + '_ {
+    let mut offset = 0;
+
+    std::iter::from_fn(move || {
+        let start = offset;
+        let byte = *instructions.get(start)?;
+
+        let op = Opcode::try_from(byte)
+            .ok()
+            .filter(|op| start + op.width() <= instructions.len());
+        let Some(op) = op else {
+            offset = instructions.len();
+            return Some(Err(start));
+        };
+
+        offset += op.width();
+        Some(Ok(Instruction {
+            offset: start,
+            op,
+            operands: read_operands(op.definition(), &instructions[start + 1..]),
+        }))
+    })
+}
+
+/// Lists `instructions` one per line with their offsets, e.g. `0003 OpConstant 1`
+#[must_use]
+pub fn disassemble(instructions: &[u8]) -> String {
+    let mut out = String::new();
+
+    for instruction in decode(instructions) {
+        match instruction {
+            Ok(instruction) => {
+                let _ = writeln!(out, "{}", instruction);
+            }
+            Err(offset) => {
+                let _ = writeln!(
+                    out,
+                    "{:04} ERROR: {}",
+                    offset,
+                    invalid(instructions, offset)
+                );
+            }
+        }
+    }
+
+    out
+}
+
+/// Why the instruction at `offset` could not be decoded
+#[must_use]
+pub fn invalid(instructions: &[u8], offset: usize) -> String {
+    match Opcode::try_from(instructions[offset]) {
+        Ok(op) => format!("{} is cut short", op),
+        Err(byte) => format!("unknown opcode {}", byte),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_make() {
+        let cases = [
+            (
+                Opcode::Constant,
+                vec![65534],
+                vec![Opcode::Constant as u8, 255, 254],
+            ),
+            (Opcode::Add, vec![], vec![Opcode::Add as u8]),
+            (
+                Opcode::GetLocal,
+                vec![255],
+                vec![Opcode::GetLocal as u8, 255],
+            ),
+            (
+                Opcode::Closure,
+                vec![65534],
+                vec![Opcode::Closure as u8, 255, 254],
+            ),
+        ];
+
+        for (op, operands, expected) in cases {
+            assert_eq!(Some(expected), make(op, &operands), "op: {}", op);
+        }
+
+        assert_eq!(None, make(Opcode::Constant, &[65536]));
+        assert_eq!(None, make(Opcode::GetLocal, &[256]));
+    }
+
+    #[test]
+    fn test_read_operands() {
+        let cases = [
+            (Opcode::Constant, vec![65535], 2),
+            (Opcode::GetLocal, vec![255], 1),
+            (Opcode::Closure, vec![65535], 2),
+        ];
+
+        for (op, operands, bytes_read) in cases {
+            let instruction = make(op, &operands).unwrap();
+            assert_eq!(1 + bytes_read, op.width());
+            assert_eq!(operands, read_operands(op.definition(), &instruction[1..]));
+        }
+    }
+
+    #[test]
+    fn test_opcode_round_trip() {
+        for op in Opcode::ALL {
+            assert_eq!(Ok(op), Opcode::try_from(op as u8));
+        }
+        assert_eq!(Err(255), Opcode::try_from(255));
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let instructions: Instructions = [
+            make(Opcode::Add, &[]),
+            make(Opcode::GetLocal, &[1]),
+            make(Opcode::Constant, &[2]),
+            make(Opcode::Constant, &[65535]),
+            make(Opcode::Closure, &[65535]),
+        ]
+        .into_iter()
+        .flatten()
+        .flatten()
+        .collect();
+
+        let expected = "\
+0000 OpAdd
+0001 OpGetLocal 1
+0003 OpConstant 2
+0006 OpConstant 65535
+0009 OpClosure 65535
+";
+
+        assert_eq!(expected, disassemble(&instructions));
+    }
+
+    #[test]
+    fn test_disassemble_invalid_instructions() {
+        let mut instructions = make(Opcode::Pop, &[]).unwrap();
+        instructions.extend(make(Opcode::Constant, &[1]).unwrap());
+        instructions.pop();
+        assert_eq!(
+            "0000 OpPop\n0001 ERROR: OpConstant is cut short\n",
+            disassemble(&instructions)
+        );
+
+        assert_eq!("0000 ERROR: unknown opcode 255\n", disassemble(&[255, 0]));
+    }
+}
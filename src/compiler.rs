@@ -0,0 +1,883 @@
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use std::rc::Rc;
+
+use crate::ast;
+use crate::builtins;
+use crate::code::{self, Instructions, Opcode};
+use crate::diagnostic::{self, Diagnostic};
+use crate::object::{CompiledFunction, Object};
+use crate::symbol_table::{Symbol, SymbolScope, SymbolTable};
+use crate::token::{Span, TokenKind};
+
+/// A compiled program, ready for the virtual machine
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bytecode {
+    /// The top level of the program, run as a function that takes no arguments
+    pub main: CompiledFunction,
+    pub constants: Vec<Object>,
+    /// The name of each global slot, for reporting a global read before it is set
+    pub globals: Vec<String>,
+}
+
+impl Bytecode {
+    /// Lists the instructions of the program and then of each function in the
+    /// constants pool, like `code::disassemble`.  Operands that refer to a
+    /// constant or a variable are followed by what they refer to, and
+    /// the instructions from each line of `source` are headed by that line
+    #[must_use]
+    pub fn disassemble(&self, source: &str) -> String {
+        let mut out = String::from("MAIN:\n");
+        self.disassemble_function(&mut out, &self.main, source);
+
+        for (index, constant) in self.constants.iter().enumerate() {
+            if let Object::CompiledFunction(function) = constant {
+                let _ = writeln!(
+                    out,
+                    "\nCONSTANT {}: {} with {} locals",
+                    index,
+                    function,
+                    function.locals.len()
+                );
+                self.disassemble_function(&mut out, function, source);
+            }
+        }
+
+        out
+    }
+
+    fn disassemble_function(&self, out: &mut String, function: &CompiledFunction, source: &str) {
+        let mut line = 0;
+
+        for instruction in code::decode(&function.instructions) {
+            let instruction = match instruction {
+                Ok(instruction) => instruction,
+                Err(offset) => {
+                    let error = code::invalid(&function.instructions, offset);
+                    let _ = writeln!(out, "{:04} ERROR: {}", offset, error);
+                    continue;
+                }
+            };
+
+            // Line 0 is an instruction with no span, such as the implicit return
+            // that ends the program
+            let span = function.span_at(instruction.offset);
+            if span.line != 0 && span.line != line {
+                line = span.line;
+                let text = source.lines().nth(line - 1).unwrap_or("");
+                let _ = writeln!(out, "--- {}: {}", line, text.trim());
+            }
+
+            match self.describe_operand(function, &instruction) {
+                Some(operand) => {
+                    let _ = writeln!(out, "{:<24} ; {}", instruction.to_string(), operand);
+                }
+                None => {
+                    let _ = writeln!(out, "{}", instruction);
+                }
+            }
+        }
+    }
+
+    fn describe_operand(
+        &self,
+        function: &CompiledFunction,
+        instruction: &code::Instruction,
+    ) -> Option<String> {
+        let operand = *instruction.operands.first()?;
+
+        match instruction.op {
+            Opcode::Constant | Opcode::Closure => match self.constants.get(operand)? {
+                Object::String(value) => Some(format!("{:?}", value)),
+                constant => Some(constant.to_string()),
+            },
+            Opcode::GetGlobal | Opcode::SetGlobal => self.globals.get(operand).cloned(),
+            Opcode::GetBuiltin => builtins::names().nth(operand).map(str::to_owned),
+            Opcode::GetLocal | Opcode::SetLocal | Opcode::GetCell | Opcode::SetCell => {
+                function.locals.get(operand).cloned()
+            }
+            Opcode::GetFree => function.free.get(operand).map(|free| free.name.clone()),
+            _ => None,
+        }
+    }
+}
+
+/// Lowers `program` to bytecode that gives the same result as `eval_program`
+///
+/// # Errors
+/// Returns an error if an integer literal does not fit in an `i64`, or the program
+/// outgrows an operand, e.g. with more than 65536 constants
+pub fn compile(program: &ast::Program) -> Result<Bytecode, Diagnostic> {
+    let mut compiler = Compiler::new();
+    compiler.declare(&program.statements, false);
+
+    for statement in &program.statements {
+        compiler.compile_statement(statement)?;
+    }
+    compiler.finish_function()?;
+
+    let scope = compiler.scopes.pop().unwrap_or_default();
+    Ok(Bytecode {
+        main: CompiledFunction {
+            instructions: scope.instructions,
+            parameters: Vec::new(),
+            locals: Vec::new(),
+            cells: Vec::new(),
+            free: Vec::new(),
+            positions: scope.positions,
+        },
+        constants: compiler.constants,
+        globals: compiler.globals,
+    })
+}
+
+#[derive(Debug, Clone, Copy)]
+struct EmittedInstruction {
+    op: Opcode,
+    position: usize,
+}
+
+/// The instructions of the function being compiled
+#[derive(Debug, Default)]
+struct CompilationScope {
+    instructions: Instructions,
+    positions: Vec<(usize, Span)>,
+    last: Option<EmittedInstruction>,
+    /// The offsets of every `OpGetLocal` and `OpSetLocal`, which become `OpGetCell`
+    /// and `OpSetCell` for a local that turns out to need a cell
+    local_accesses: Vec<usize>,
+}
+
+struct Compiler {
+    constants: Vec<Object>,
+    globals: Vec<String>,
+    symbol_table: SymbolTable,
+    /// The outermost scope is the program, with one more for each function literal
+    /// being compiled
+    scopes: Vec<CompilationScope>,
+    /// The statement being compiled.  Instructions without a more precise span are
+    /// recorded against it, and it is blamed when an operand overflows
+    span: Span,
+}
+
+impl Compiler {
+    fn new() -> Self {
+        let mut symbol_table = SymbolTable::new();
+        for (index, name) in builtins::names().enumerate() {
+            symbol_table.define_builtin(index, name);
+        }
+
+        Self {
+            constants: Vec::new(),
+            globals: Vec::new(),
+            symbol_table,
+            scopes: vec![CompilationScope::default()],
+            span: Span::default(),
+        }
+    }
+
+    /// Declares every name `statements` bind with `let`, including in `if` blocks but
+    /// not in function literals, before any of them is compiled.  The evaluator looks
+    /// a name up when it is used, so a function can refer to a `let` of the scope it
+    /// is written in that comes after it.  `conditional` is whether the statements
+    /// are in an `if` block, as every `let` found within an expression is
+    fn declare(&mut self, statements: &[ast::Statement], conditional: bool) {
+        for statement in statements {
+            let expression = match statement {
+                ast::Statement::Let(_, ident, value) => {
+                    let symbol = self.symbol_table.declare(&ident.value, conditional);
+                    self.record_global(&symbol);
+                    value
+                }
+                ast::Statement::Return(_, value) | ast::Statement::Expression(_, value) => value,
+            };
+            self.declare_in(expression);
+        }
+    }
+
+    fn declare_in(&mut self, expression: &ast::Expression) {
+        match expression {
+            ast::Expression::Prefix { right, .. } => self.declare_in(right),
+            ast::Expression::Infix { left, right, .. }
+            | ast::Expression::Index {
+                left, index: right, ..
+            } => {
+                self.declare_in(left);
+                self.declare_in(right);
+            }
+            ast::Expression::If {
+                condition,
+                consequence,
+                alternative,
+                ..
+            } => {
+                self.declare_in(condition);
+                self.declare(&consequence.statements, true);
+                if let Some(alternative) = alternative {
+                    self.declare(&alternative.statements, true);
+                }
+            }
+            ast::Expression::Call { function, args, .. } => {
+                self.declare_in(function);
+                for arg in args {
+                    self.declare_in(arg);
+                }
+            }
+            ast::Expression::ArrayLiteral { elements, .. } => {
+                for element in elements {
+                    self.declare_in(element);
+                }
+            }
+            ast::Expression::HashLiteral { pairs, .. } => {
+                for (key, value) in pairs {
+                    self.declare_in(key);
+                    self.declare_in(value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn compile_statement(&mut self, statement: &ast::Statement) -> Result<(), Diagnostic> {
+        let outer = std::mem::replace(&mut self.span, statement.span());
+
+        match statement {
+            ast::Statement::Expression(_, expression) => {
+                self.compile_expression(expression)?;
+                self.emit(Opcode::Pop, &[])?;
+            }
+            ast::Statement::Let(_, ident, value) => {
+                // The name was declared with its scope, so a function can call itself
+                // through it, and `let x = x + 1` reads the old `x` from the same slot
+                self.compile_expression(value)?;
+
+                let symbol = self.symbol_table.define(&ident.value);
+                self.record_global(&symbol);
+                match symbol.scope {
+                    SymbolScope::Global => self.emit(Opcode::SetGlobal, &[symbol.index])?,
+                    _ => self.emit_local_access(Opcode::SetLocal, symbol.index, self.span)?,
+                };
+            }
+            ast::Statement::Return(_, value) => {
+                self.compile_expression(value)?;
+                self.emit(Opcode::ReturnValue, &[])?;
+            }
+        }
+
+        // A function literal's body is made of statements, after which the rest of
+        // the enclosing statement is compiled
+        self.span = outer;
+        Ok(())
+    }
+
+    fn compile_expression(&mut self, expression: &ast::Expression) -> Result<(), Diagnostic> {
+        match expression {
+            ast::Expression::Integer(token) => {
+                let value = token.literal().parse::<i64>().map_err(|_| {
+                    Diagnostic::error(
+                        diagnostic::INVALID_NUMBER,
+                        format!("could not parse {} as integer", token.literal()),
+                        token.span,
+                    )
+                })?;
+                self.emit_constant(Object::Integer(value))?;
+            }
+            ast::Expression::Float(token) => {
+                let value = token.literal().parse::<f64>().map_err(|_| {
+                    Diagnostic::error(
+                        diagnostic::INVALID_NUMBER,
+                        format!("could not parse {} as float", token.literal()),
+                        token.span,
+                    )
+                })?;
+                self.emit_constant(Object::Float(value))?;
+            }
+            ast::Expression::Boolean(token) => {
+                let op = if token.kind == TokenKind::True {
+                    Opcode::True
+                } else {
+                    Opcode::False
+                };
+                self.emit(op, &[])?;
+            }
+            ast::Expression::StringLiteral(token) => {
+                self.emit_constant(Object::String(token.literal().to_owned()))?;
+            }
+            ast::Expression::Identifier(token) => {
+                // A name bound nowhere in scope is given a global that is never
+                // set, so reading it is a runtime error that can name it
+                let symbol = match self.symbol_table.resolve(token.literal()) {
+                    Some(symbol) => symbol,
+                    None => {
+                        let symbol = self.symbol_table.define_global(token.literal());
+                        self.record_global(&symbol);
+                        symbol
+                    }
+                };
+                self.load_symbol(&symbol, token.span)?;
+            }
+            ast::Expression::Prefix { token, right } => {
+                self.compile_expression(right)?;
+                let op = match token.kind {
+                    TokenKind::Minus => Opcode::Minus,
+                    _ => Opcode::Bang,
+                };
+                self.emit_at(op, &[], expression.span())?;
+            }
+            ast::Expression::Infix { token, left, right } => {
+                self.compile_expression(left)?;
+                self.compile_expression(right)?;
+                let op = match token.kind {
+                    TokenKind::Plus => Opcode::Add,
+                    TokenKind::Minus => Opcode::Sub,
+                    TokenKind::Asterisk => Opcode::Mul,
+                    TokenKind::Slash => Opcode::Div,
+                    TokenKind::Eq => Opcode::Equal,
+                    TokenKind::NotEq => Opcode::NotEqual,
+                    TokenKind::Gt => Opcode::GreaterThan,
+                    _ => Opcode::LessThan,
+                };
+                self.emit_at(op, &[], expression.span())?;
+            }
+            ast::Expression::If {
+                condition,
+                consequence,
+                alternative,
+                ..
+            } => {
+                self.compile_expression(condition)?;
+                // Both jumps are emitted with a placeholder and patched once the
+                // offset they jump to is known
+                let jump_not_truthy = self.emit(Opcode::JumpNotTruthy, &[0])?;
+                self.compile_branch(Some(consequence))?;
+                let jump = self.emit(Opcode::Jump, &[0])?;
+
+                let after_consequence = self.scope().instructions.len();
+                self.change_operand(jump_not_truthy, after_consequence)?;
+
+                self.compile_branch(alternative.as_ref())?;
+
+                let after_alternative = self.scope().instructions.len();
+                self.change_operand(jump, after_alternative)?;
+            }
+            ast::Expression::FunctionLiteral { params, body, .. } => {
+                self.compile_function(params, body)?;
+            }
+            ast::Expression::Call { function, args, .. } => {
+                self.compile_expression(function)?;
+                for arg in args {
+                    self.compile_expression(arg)?;
+                }
+                self.emit_at(Opcode::Call, &[args.len()], expression.span())?;
+            }
+            ast::Expression::ArrayLiteral { elements, .. } => {
+                for element in elements {
+                    self.compile_expression(element)?;
+                }
+                self.emit(Opcode::Array, &[elements.len()])?;
+            }
+            ast::Expression::HashLiteral { pairs, .. } => {
+                for (key, value) in pairs {
+                    self.compile_expression(key)?;
+                    self.compile_expression(value)?;
+                }
+                self.emit_at(Opcode::Hash, &[pairs.len() * 2], expression.span())?;
+            }
+            ast::Expression::Index { left, index, .. } => {
+                self.compile_expression(left)?;
+                self.compile_expression(index)?;
+                self.emit_at(Opcode::Index, &[], expression.span())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Compiles one branch of an `if` so that it leaves its value on the stack, which
+    /// is `null` for a missing or empty branch or one ending in a `let`
+    fn compile_branch(&mut self, block: Option<&ast::BlockStatement>) -> Result<(), Diagnostic> {
+        for statement in block.map_or(&[][..], |block| &block.statements) {
+            self.compile_statement(statement)?;
+        }
+
+        if self.last_instruction_is(Opcode::Pop) {
+            self.remove_last_instruction();
+        } else {
+            self.emit(Opcode::Null, &[])?;
+        }
+
+        Ok(())
+    }
+
+    fn compile_function(
+        &mut self,
+        params: &[ast::Identifier],
+        body: &ast::BlockStatement,
+    ) -> Result<(), Diagnostic> {
+        self.scopes.push(CompilationScope::default());
+        self.symbol_table = SymbolTable::new_enclosed(std::mem::take(&mut self.symbol_table));
+
+        for param in params {
+            self.symbol_table.define(&param.value);
+        }
+        self.declare(&body.statements, false);
+
+        for statement in &body.statements {
+            self.compile_statement(statement)?;
+        }
+        self.finish_function()?;
+
+        let mut symbol_table = std::mem::take(&mut self.symbol_table);
+        let locals = std::mem::take(&mut symbol_table.definitions);
+        let free = std::mem::take(&mut symbol_table.free);
+        let cells = std::mem::take(&mut symbol_table.cells);
+        self.symbol_table = symbol_table.into_outer().unwrap_or_default();
+        let mut scope = self.scopes.pop().unwrap_or_default();
+        use_cells(&mut scope, &cells);
+
+        let function = CompiledFunction {
+            instructions: scope.instructions,
+            parameters: params.iter().map(|param| param.value.clone()).collect(),
+            locals,
+            cells: cells.into_iter().collect(),
+            free,
+            positions: scope.positions,
+        };
+        let index = self.add_constant(Object::CompiledFunction(Rc::new(function)));
+        self.emit(Opcode::Closure, &[index])?;
+
+        Ok(())
+    }
+
+    /// Makes the current function return the value of its last expression statement,
+    /// or `null` if it ends any other way
+    fn finish_function(&mut self) -> Result<(), Diagnostic> {
+        if self.last_instruction_is(Opcode::Pop) {
+            let position = self.scope().last.map_or(0, |last| last.position);
+            self.scope_mut().instructions[position] = Opcode::ReturnValue as u8;
+            self.scope_mut().last = Some(EmittedInstruction {
+                op: Opcode::ReturnValue,
+                position,
+            });
+        } else if !self.last_instruction_is(Opcode::ReturnValue) {
+            self.emit(Opcode::Return, &[])?;
+        }
+
+        Ok(())
+    }
+
+    fn load_symbol(&mut self, symbol: &Symbol, span: Span) -> Result<(), Diagnostic> {
+        match symbol.scope {
+            SymbolScope::Global => self.emit_at(Opcode::GetGlobal, &[symbol.index], span)?,
+            SymbolScope::Local => self.emit_local_access(Opcode::GetLocal, symbol.index, span)?,
+            SymbolScope::Builtin => self.emit(Opcode::GetBuiltin, &[symbol.index])?,
+            SymbolScope::Free => self.emit_at(Opcode::GetFree, &[symbol.index], span)?,
+        };
+
+        Ok(())
+    }
+
+    fn record_global(&mut self, symbol: &Symbol) {
+        if symbol.scope == SymbolScope::Global && symbol.index == self.globals.len() {
+            self.globals.push(symbol.name.clone());
+        }
+    }
+
+    /// Adds to the constants pool and returns the index.  An index too large for an
+    /// operand is caught when it is emitted
+    fn add_constant(&mut self, constant: Object) -> usize {
+        self.constants.push(constant);
+        self.constants.len() - 1
+    }
+
+    fn emit_constant(&mut self, constant: Object) -> Result<usize, Diagnostic> {
+        let index = self.add_constant(constant);
+        self.emit(Opcode::Constant, &[index])
+    }
+
+    /// Appends an instruction from the statement being compiled to the current
+    /// function and returns its offset
+    fn emit(&mut self, op: Opcode, operands: &[usize]) -> Result<usize, Diagnostic> {
+        self.emit_at(op, operands, self.span)
+    }
+
+    /// Like `emit`, for an instruction that can raise a runtime error blamed on `span`
+    fn emit_at(&mut self, op: Opcode, operands: &[usize], span: Span) -> Result<usize, Diagnostic> {
+        let instruction = code::make(op, operands).ok_or_else(|| self.limit_error(op))?;
+
+        let scope = self.scope_mut();
+        let position = scope.instructions.len();
+        scope.instructions.extend(instruction);
+        scope.positions.push((position, span));
+        scope.last = Some(EmittedInstruction { op, position });
+
+        Ok(position)
+    }
+
+    /// Emits `OpGetLocal` or `OpSetLocal`, remembering it in case the local is later
+    /// found to need a cell
+    fn emit_local_access(
+        &mut self,
+        op: Opcode,
+        slot: usize,
+        span: Span,
+    ) -> Result<usize, Diagnostic> {
+        let position = self.emit_at(op, &[slot], span)?;
+        self.scope_mut().local_accesses.push(position);
+        Ok(position)
+    }
+
+    fn change_operand(&mut self, position: usize, operand: usize) -> Result<(), Diagnostic> {
+        let op = Opcode::try_from(self.scope().instructions[position])
+            .unwrap_or_else(|byte| unreachable!("emitted unknown opcode {}", byte));
+        let instruction = code::make(op, &[operand]).ok_or_else(|| self.limit_error(op))?;
+
+        let scope = self.scope_mut();
+        scope.instructions[position..position + instruction.len()].copy_from_slice(&instruction);
+
+        Ok(())
+    }
+
+    fn last_instruction_is(&self, op: Opcode) -> bool {
+        self.scope().last.is_some_and(|last| last.op == op)
+    }
+
+    /// Removes the last instruction, which must not be the target of a jump
+    fn remove_last_instruction(&mut self) {
+        let scope = self.scope_mut();
+        if let Some(last) = scope.last.take() {
+            scope.instructions.truncate(last.position);
+            scope.positions.pop();
+        }
+    }
+
+    fn limit_error(&self, op: Opcode) -> Diagnostic {
+        let what = match op {
+            Opcode::Constant | Opcode::Closure => "constants",
+            Opcode::GetGlobal | Opcode::SetGlobal => "global variables",
+            Opcode::GetLocal | Opcode::SetLocal => "local variables",
+            Opcode::GetFree => "captured variables",
+            Opcode::Call => "arguments",
+            Opcode::Array | Opcode::Hash => "elements",
+            Opcode::Jump | Opcode::JumpNotTruthy => "instructions in one function",
+            _ => "operands",
+        };
+
+        Diagnostic::error(
+            diagnostic::LIMIT_EXCEEDED,
+            format!("too many {} to compile", what),
+            self.span,
+        )
+    }
+
+    fn scope(&self) -> &CompilationScope {
+        self.scopes
+            .last()
+            .expect("the program scope is never popped")
+    }
+
+    fn scope_mut(&mut self) -> &mut CompilationScope {
+        self.scopes
+            .last_mut()
+            .expect("the program scope is never popped")
+    }
+}
+
+/// Turns the accesses of the locals in `cells` into cell accesses, which have the
+/// same width so no jump needs to move
+fn use_cells(scope: &mut CompilationScope, cells: &BTreeSet<usize>) {
+    for &position in &scope.local_accesses {
+        let slot = usize::from(scope.instructions[position + 1]);
+        if !cells.contains(&slot) {
+            continue;
+        }
+
+        scope.instructions[position] = if scope.instructions[position] == Opcode::GetLocal as u8 {
+            Opcode::GetCell as u8
+        } else {
+            Opcode::SetCell as u8
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer;
+    use crate::object::{Capture, FreeVariable};
+    use crate::parser;
+
+    fn compile_input(input: &str) -> Result<Bytecode, Diagnostic> {
+        let lexer = lexer::Lexer::new(input);
+        let mut parser = parser::Parser::new(lexer);
+        let program = parser.parse_program();
+        assert!(parser.errors.is_empty(), "{:?}", parser.errors);
+        compile(&program)
+    }
+
+    fn instructions(parts: &[(Opcode, &[usize])]) -> Instructions {
+        parts
+            .iter()
+            .flat_map(|(op, operands)| code::make(*op, operands).unwrap())
+            .collect()
+    }
+
+    fn function(constant: &Object) -> &CompiledFunction {
+        match constant {
+            Object::CompiledFunction(function) => function,
+            other => panic!("expected a compiled function, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_integer_arithmetic() {
+        let bytecode = compile_input("1 + 2; -3").unwrap();
+
+        let expected = instructions(&[
+            (Opcode::Constant, &[0]),
+            (Opcode::Constant, &[1]),
+            (Opcode::Add, &[]),
+            (Opcode::Pop, &[]),
+            (Opcode::Constant, &[2]),
+            (Opcode::Minus, &[]),
+            (Opcode::ReturnValue, &[]),
+        ]);
+        assert_eq!(
+            code::disassemble(&expected),
+            code::disassemble(&bytecode.main.instructions)
+        );
+        assert_eq!(
+            vec![Object::Integer(1), Object::Integer(2), Object::Integer(3)],
+            bytecode.constants
+        );
+    }
+
+    #[test]
+    fn test_conditionals() {
+        let bytecode = compile_input("if (true) { 10 }; 3333;").unwrap();
+
+        let expected = instructions(&[
+            (Opcode::True, &[]),
+            (Opcode::JumpNotTruthy, &[10]),
+            (Opcode::Constant, &[0]),
+            (Opcode::Jump, &[11]),
+            (Opcode::Null, &[]),
+            (Opcode::Pop, &[]),
+            (Opcode::Constant, &[1]),
+            (Opcode::ReturnValue, &[]),
+        ]);
+        assert_eq!(
+            code::disassemble(&expected),
+            code::disassemble(&bytecode.main.instructions)
+        );
+    }
+
+    #[test]
+    fn test_global_let_statements() {
+        let bytecode = compile_input("let one = 1; let two = one; two;").unwrap();
+
+        let expected = instructions(&[
+            (Opcode::Constant, &[0]),
+            (Opcode::SetGlobal, &[0]),
+            (Opcode::GetGlobal, &[0]),
+            (Opcode::SetGlobal, &[1]),
+            (Opcode::GetGlobal, &[1]),
+            (Opcode::ReturnValue, &[]),
+        ]);
+        assert_eq!(
+            code::disassemble(&expected),
+            code::disassemble(&bytecode.main.instructions)
+        );
+        assert_eq!(vec!["one", "two"], bytecode.globals);
+    }
+
+    #[test]
+    fn test_closures_capture_free_variables() {
+        let bytecode = compile_input("fn(a) { fn(b) { a + b + len } }").unwrap();
+
+        let inner = function(&bytecode.constants[0]);
+        let expected = instructions(&[
+            (Opcode::GetFree, &[0]),
+            (Opcode::GetLocal, &[0]),
+            (Opcode::Add, &[]),
+            (Opcode::GetBuiltin, &[0]),
+            (Opcode::Add, &[]),
+            (Opcode::ReturnValue, &[]),
+        ]);
+        assert_eq!(
+            code::disassemble(&expected),
+            code::disassemble(&inner.instructions)
+        );
+
+        assert_eq!(
+            vec![FreeVariable {
+                name: "a".to_owned(),
+                capture: Capture::Local(0),
+            }],
+            inner.free
+        );
+        assert!(inner.cells.is_empty());
+
+        let outer = function(&bytecode.constants[1]);
+        let expected = instructions(&[(Opcode::Closure, &[0]), (Opcode::ReturnValue, &[])]);
+        assert_eq!(
+            code::disassemble(&expected),
+            code::disassemble(&outer.instructions)
+        );
+        assert_eq!(vec!["a"], outer.locals);
+        assert_eq!(vec!["a"], outer.parameters);
+        assert_eq!(vec![0], outer.cells);
+    }
+
+    #[test]
+    fn test_later_lets_are_in_scope() {
+        let bytecode = compile_input("fn() { let f = fn() { g }; let g = 1; f }").unwrap();
+
+        let f = function(&bytecode.constants[0]);
+        let expected = instructions(&[(Opcode::GetFree, &[0]), (Opcode::ReturnValue, &[])]);
+        assert_eq!(
+            code::disassemble(&expected),
+            code::disassemble(&f.instructions)
+        );
+
+        // Only `g` is captured, so only it is kept in a cell
+        let outer = function(&bytecode.constants[2]);
+        let expected = instructions(&[
+            (Opcode::Closure, &[0]),
+            (Opcode::SetLocal, &[0]),
+            (Opcode::Constant, &[1]),
+            (Opcode::SetCell, &[1]),
+            (Opcode::GetLocal, &[0]),
+            (Opcode::ReturnValue, &[]),
+        ]);
+        assert_eq!(
+            code::disassemble(&expected),
+            code::disassemble(&outer.instructions)
+        );
+        assert_eq!(vec!["f", "g"], outer.locals);
+        assert_eq!(vec![1], outer.cells);
+        assert_eq!(Capture::Local(1), f.free[0].capture);
+    }
+
+    #[test]
+    fn test_recursive_functions() {
+        let bytecode = compile_input("let f = fn(x) { f(x - 1) };").unwrap();
+
+        let f = function(&bytecode.constants[1]);
+        let expected = instructions(&[
+            (Opcode::GetGlobal, &[0]),
+            (Opcode::GetLocal, &[0]),
+            (Opcode::Constant, &[0]),
+            (Opcode::Sub, &[]),
+            (Opcode::Call, &[1]),
+            (Opcode::ReturnValue, &[]),
+        ]);
+        assert_eq!(
+            code::disassemble(&expected),
+            code::disassemble(&f.instructions)
+        );
+    }
+
+    #[test]
+    fn test_empty_function_returns_null() {
+        let bytecode = compile_input("fn() { }").unwrap();
+
+        let expected = instructions(&[(Opcode::Return, &[])]);
+        assert_eq!(expected, function(&bytecode.constants[0]).instructions);
+    }
+
+    #[test]
+    fn test_positions() {
+        let bytecode = compile_input("let x = 1;\nlet f = fn() {\n  x + true\n};").unwrap();
+
+        // The addition can fail, so it has the span of its expression rather than
+        // its statement.  The closure is back on the line of the outer statement
+        let f = function(&bytecode.constants[1]);
+        let cases = [
+            (&bytecode.main, 0, (1, 1, 9)),
+            (f, 0, (3, 3, 1)),
+            (f, 3, (3, 3, 8)),
+            (f, 4, (3, 3, 8)),
+            (&bytecode.main, 6, (2, 1, 27)),
+        ];
+
+        for (function, offset, (line, column, len)) in cases {
+            let span = function.span_at(offset);
+            assert_eq!(
+                (line, column, len),
+                (span.line, span.column, span.end - span.start),
+                "offset: {}\n{}",
+                offset,
+                code::disassemble(&function.instructions)
+            );
+        }
+        assert_eq!(
+            bytecode.main.positions.len(),
+            code::disassemble(&bytecode.main.instructions)
+                .lines()
+                .count()
+        );
+    }
+
+    #[test]
+    fn test_disassemble() {
+        let source = "\
+let greet = fn(name) {
+  let greeting = \"hi \" + name;
+  len(greeting)
+};
+greet(\"bob\")";
+        let bytecode = compile_input(source).unwrap();
+
+        let expected = "\
+MAIN:
+--- 1: let greet = fn(name) {
+0000 OpClosure 1         ; fn(name) { ... }
+0003 OpSetGlobal 0       ; greet
+--- 5: greet(\"bob\")
+0006 OpGetGlobal 0       ; greet
+0009 OpConstant 2        ; \"bob\"
+0012 OpCall 1
+0014 OpReturnValue
+
+CONSTANT 1: fn(name) { ... } with 2 locals
+--- 2: let greeting = \"hi \" + name;
+0000 OpConstant 0        ; \"hi \"
+0003 OpGetLocal 0        ; name
+0005 OpAdd
+0006 OpSetLocal 1        ; greeting
+--- 3: len(greeting)
+0008 OpGetBuiltin 0      ; len
+0010 OpGetLocal 1        ; greeting
+0012 OpCall 1
+0014 OpReturnValue
+";
+        assert_eq!(expected, bytecode.disassemble(source));
+    }
+
+    #[test]
+    fn test_disassemble_free_variables() {
+        let source = "fn(a) { let b = 1; fn() { a + b } }";
+        let bytecode = compile_input(source).unwrap();
+
+        let expected = "\
+CONSTANT 1: fn() { ... } with 0 locals
+--- 1: fn(a) { let b = 1; fn() { a + b } }
+0000 OpGetFree 0         ; a
+0002 OpGetFree 1         ; b
+0004 OpAdd
+0005 OpReturnValue
+";
+        let disassembly = bytecode.disassemble(source);
+        assert!(disassembly.contains(expected), "{}", disassembly);
+    }
+
+    #[test]
+    fn test_too_many_elements() {
+        let input = format!("[{}]", vec!["1"; 65536].join(", "));
+
+        let err = compile_input(&input).unwrap_err();
+        assert_eq!(diagnostic::LIMIT_EXCEEDED, err.code);
+        assert_eq!("too many elements to compile", err.message);
+    }
+}
@@ -0,0 +1,286 @@
+use core::fmt;
+use std::fmt::Write;
+
+use crate::token::Span;
+
+// Error codes, rendered as `E0001` and so on
+
+// Lexer and parser errors
+pub const UNEXPECTED_TOKEN: u16 = 1;
+pub const EXPECTED_EXPRESSION: u16 = 2;
+pub const UNCLOSED_DELIMITER: u16 = 3;
+pub const INVALID_NUMBER: u16 = 4;
+pub const ILLEGAL_CHARACTER: u16 = 5;
+pub const UNTERMINATED_STRING: u16 = 6;
+pub const INVALID_ESCAPE: u16 = 7;
+pub const TOO_DEEPLY_NESTED: u16 = 8;
+
+// Compiler errors
+pub const LIMIT_EXCEEDED: u16 = 50;
+
+// Evaluation errors
+pub const RUNTIME_ERROR: u16 = 100;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Error => write!(f, "error"),
+            Self::Warning => write!(f, "warning"),
+            Self::Note => write!(f, "note"),
+        }
+    }
+}
+
+/// Extra context attached to a diagnostic, pointing at a related piece of source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Label {
+    pub span: Span,
+    pub message: String,
+}
+
+/// A problem found in Monkey source, along with everything needed to show the user
+/// where it is
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub code: u16,
+    pub message: String,
+    /// The source the diagnostic is about
+    pub span: Span,
+    /// Secondary spans that explain the primary one
+    pub labels: Vec<Label>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    #[must_use]
+    pub fn error(code: u16, message: impl Into<String>, span: Span) -> Self {
+        Self {
+            severity: Severity::Error,
+            code,
+            message: message.into(),
+            span,
+            labels: Vec::new(),
+            notes: Vec::new(),
+        }
+    }
+
+    #[must_use]
+    pub fn with_label(mut self, span: Span, message: impl Into<String>) -> Self {
+        self.labels.push(Label {
+            span,
+            message: message.into(),
+        });
+        self
+    }
+
+    #[must_use]
+    pub fn with_note(mut self, note: impl Into<String>) -> Self {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Renders the diagnostic in the style of rustc: a header, the location, then the
+    /// offending source lines with the primary span underlined by `^` and each label
+    /// underlined by `-`
+    ///
+    /// ```text
+    /// error[E0001]: expected next token to be IDENT, got INT instead
+    ///  --> script.mk:3:5
+    ///   |
+    /// 3 | let 838383;
+    ///   | --- ^^^^^^
+    ///   | in this let statement
+    /// ```
+    #[must_use]
+    pub fn render(&self, name: &str, source: &str) -> String {
+        let mut lines: Vec<usize> = std::iter::once(self.span.line)
+            .chain(self.labels.iter().map(|label| label.span.line))
+            .collect();
+        lines.sort_unstable();
+        lines.dedup();
+
+        let gutter = lines.last().map_or(1, |line| line.to_string().len());
+        let pad = " ".repeat(gutter);
+
+        let mut out = String::new();
+        let _ = writeln!(
+            out,
+            "{}[E{:04}]: {}",
+            self.severity, self.code, self.message
+        );
+        let _ = writeln!(out, "{}--> {}:{}", pad, name, self.span);
+        let _ = writeln!(out, "{} |", pad);
+
+        for line in lines {
+            let text = source_line(source, line);
+            let _ = writeln!(out, "{:>gutter$} | {}", line, text.trim_end());
+
+            let mut marks: Vec<(Span, char, &str)> = Vec::new();
+            if self.span.line == line {
+                marks.push((self.span, '^', ""));
+            }
+            for label in self.labels.iter().filter(|label| label.span.line == line) {
+                marks.push((label.span, '-', &label.message));
+            }
+            marks.sort_by_key(|(span, _, _)| span.column);
+
+            let mut underline = String::new();
+            for (span, mark, _) in &marks {
+                let start = span.column.saturating_sub(1);
+                let width = underline_width(source, *span);
+                while underline.chars().count() < start {
+                    // Keep tabs so the carets line up with the source above
+                    let char = text.chars().nth(underline.chars().count()).unwrap_or(' ');
+                    underline.push(if char == '\t' { '\t' } else { ' ' });
+                }
+                underline.extend(std::iter::repeat_n(*mark, width));
+            }
+            let _ = writeln!(out, "{} | {}", pad, underline);
+
+            for (span, _, message) in marks.iter().rev().filter(|(_, _, m)| !m.is_empty()) {
+                let indent = " ".repeat(span.column.saturating_sub(1));
+                let _ = writeln!(out, "{} | {}{}", pad, indent, message);
+            }
+        }
+
+        for note in &self.notes {
+            let _ = writeln!(out, "{} = note: {}", pad, note);
+        }
+
+        out
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}[E{:04}]: {}: {}",
+            self.severity, self.code, self.span, self.message
+        )
+    }
+}
+
+impl std::error::Error for Diagnostic {}
+
+fn source_line(source: &str, line: usize) -> &str {
+    source.lines().nth(line.saturating_sub(1)).unwrap_or("")
+}
+
+/// Number of characters to underline for `span`, clipped to the end of its first
+/// line and at least one so that empty spans such as EOF remain visible.  A span
+/// that does not fall on character boundaries, as when it came from another
+/// source, gets just the one
+fn underline_width(source: &str, span: Span) -> usize {
+    let start = span.start.min(source.len());
+    let Some(rest) = source.get(start..) else {
+        return 1;
+    };
+    let line_end = rest.find('\n').map_or(source.len(), |idx| start + idx);
+    let end = span.end.clamp(start, line_end);
+
+    source
+        .get(start..end)
+        .map_or(0, |text| text.chars().count())
+        .max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(start: usize, end: usize, line: usize, column: usize) -> Span {
+        Span {
+            start,
+            end,
+            line,
+            column,
+        }
+    }
+
+    #[test]
+    fn test_render_primary_span() {
+        let source = "let x = 5;\nlet y = x +;\n";
+        let diagnostic = Diagnostic::error(
+            EXPECTED_EXPRESSION,
+            "expected an expression",
+            span(21, 22, 2, 11),
+        )
+        .with_note("an operator must be followed by its right operand");
+
+        assert_eq!(
+            diagnostic.render("test.mk", source),
+            "error[E0002]: expected an expression\n \
+             --> test.mk:2:11\n  \
+             |\n\
+             2 | let y = x +;\n  \
+             |           ^\n  \
+             = note: an operator must be followed by its right operand\n"
+        );
+    }
+
+    #[test]
+    fn test_render_labels() {
+        let source = "let 838383;";
+        let diagnostic =
+            Diagnostic::error(UNEXPECTED_TOKEN, "expected identifier", span(4, 10, 1, 5))
+                .with_label(span(0, 3, 1, 1), "in this let statement");
+
+        assert_eq!(
+            diagnostic.render("<repl>", source),
+            "error[E0001]: expected identifier\n \
+             --> <repl>:1:5\n  \
+             |\n\
+             1 | let 838383;\n  \
+             | --- ^^^^^^\n  \
+             | in this let statement\n"
+        );
+    }
+
+    #[test]
+    fn test_render_multiple_lines_and_multibyte() {
+        let source = "let é = fn(x) {\n\tx + ü\n}";
+        let diagnostic =
+            Diagnostic::error(RUNTIME_ERROR, "identifier not found: ü", span(22, 24, 2, 6))
+                .with_label(span(0, 3, 1, 1), "while evaluating this");
+
+        assert_eq!(
+            diagnostic.render("<repl>", source),
+            "error[E0100]: identifier not found: ü\n \
+             --> <repl>:2:6\n  \
+             |\n\
+             1 | let é = fn(x) {\n  \
+             | ---\n  \
+             | while evaluating this\n\
+             2 | \tx + ü\n  \
+             | \t    ^\n"
+        );
+    }
+
+    #[test]
+    fn test_render_span_from_another_source() {
+        let source = "xxéééééééééééé\n";
+        let diagnostic = Diagnostic::error(
+            RUNTIME_ERROR,
+            "type mismatch: INTEGER + BOOLEAN",
+            span(19, 27, 2, 9),
+        );
+
+        assert_eq!(
+            diagnostic.render("test.mk", source),
+            "error[E0100]: type mismatch: INTEGER + BOOLEAN\n \
+             --> test.mk:2:9\n  \
+             |\n\
+             2 | \n  \
+             |         ^\n"
+        );
+    }
+}
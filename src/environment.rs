@@ -0,0 +1,122 @@
+use std::cell::RefCell;
+use std::collections::hash_map::HashMap;
+use std::rc::Rc;
+
+use crate::integer_mode::IntegerMode;
+use crate::interrupt::InterruptHandle;
+use crate::object::Object;
+
+/// A single lexical scope.  Lookups that miss fall through to the enclosing scope, so
+/// a function body sees the bindings of the scope it was defined in
+#[derive(Debug, Default)]
+pub struct Environment {
+    store: HashMap<String, Object>,
+    outer: Option<Rc<RefCell<Environment>>>,
+    /// Chosen for the outermost scope and shared by every scope nested in it
+    integer_mode: IntegerMode,
+    interrupt: InterruptHandle,
+}
+
+impl Environment {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn with_integer_mode(integer_mode: IntegerMode) -> Self {
+        Self {
+            integer_mode,
+            ..Self::default()
+        }
+    }
+
+    /// Creates a scope nested inside `outer`, e.g. for the body of a function call
+    #[must_use]
+    pub fn new_enclosed(outer: Rc<RefCell<Self>>) -> Self {
+        let integer_mode = outer.borrow().integer_mode;
+        let interrupt = outer.borrow().interrupt.clone();
+        Self {
+            store: HashMap::new(),
+            outer: Some(outer),
+            integer_mode,
+            interrupt,
+        }
+    }
+
+    /// Makes evaluation in this scope stop when `interrupt` is triggered, e.g. so a
+    /// new scope answers to the same Ctrl-C handler as the one it replaces
+    #[must_use]
+    pub fn with_interrupt_handle(mut self, interrupt: InterruptHandle) -> Self {
+        self.interrupt = interrupt;
+        self
+    }
+
+    #[must_use]
+    pub const fn integer_mode(&self) -> IntegerMode {
+        self.integer_mode
+    }
+
+    #[must_use]
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    /// Clears a pending interrupt and returns whether there was one
+    pub(crate) fn take_interrupt(&self) -> bool {
+        self.interrupt.take()
+    }
+
+    #[must_use]
+    pub fn get(&self, name: &str) -> Option<Object> {
+        match self.store.get(name) {
+            Some(value) => Some(value.clone()),
+            None => self
+                .outer
+                .as_ref()
+                .and_then(|outer| outer.borrow().get(name)),
+        }
+    }
+
+    /// The bindings made directly in this scope, in no particular order
+    pub fn bindings(&self) -> impl Iterator<Item = (&str, &Object)> {
+        self.store
+            .iter()
+            .map(|(name, value)| (name.as_str(), value))
+    }
+
+    /// Binds `name` in this scope, shadowing any binding of the same name in an
+    /// enclosing scope
+    pub fn set(&mut self, name: &str, value: Object) {
+        self.store.insert(name.to_owned(), value);
+    }
+
+    /// Removes every binding made directly in this scope
+    pub fn clear(&mut self) {
+        self.store.clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_enclosed_lookup() {
+        let outer = Rc::new(RefCell::new(Environment::new()));
+        outer.borrow_mut().set("x", Object::Integer(1));
+        outer.borrow_mut().set("y", Object::Integer(2));
+
+        let mut inner = Environment::new_enclosed(Rc::clone(&outer));
+        inner.set("x", Object::Integer(10));
+
+        assert_eq!(Some(Object::Integer(10)), inner.get("x"));
+        assert_eq!(Some(Object::Integer(2)), inner.get("y"));
+        assert_eq!(None, inner.get("z"));
+        assert_eq!(Some(Object::Integer(1)), outer.borrow().get("x"));
+
+        let mut names: Vec<&str> = inner.bindings().map(|(name, _)| name).collect();
+        names.sort_unstable();
+        assert_eq!(vec!["x"], names);
+    }
+}
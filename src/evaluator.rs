@@ -0,0 +1,1073 @@
+use core::fmt;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use num_bigint::{BigInt, Sign};
+
+use crate::ast;
+use crate::builtins::{self, Builtin};
+use crate::environment::Environment;
+pub use crate::integer_mode::IntegerMode;
+use crate::object::{self, Object};
+use crate::token::{self, TokenKind};
+
+/// How deeply function calls may nest before raising a "stack overflow" error, the
+/// same limit as the virtual machine's
+pub(crate) const MAX_CALL_DEPTH: usize = 10_000;
+
+/// Free stack the evaluator keeps below it before each call, and how much more to
+/// allocate when there is less.  A single Monkey call goes through several Rust
+/// frames, so the thread's own stack would run out long before `MAX_CALL_DEPTH`
+const STACK_RED_ZONE: usize = 128 * 1024;
+const STACK_GROWTH: usize = 4 * 1024 * 1024;
+
+thread_local! {
+    /// The number of Monkey function calls in progress on this thread
+    static CALL_DEPTH: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Evaluates every statement of `program` in order and returns the value of the
+/// last one.  A `return` or a runtime error stops evaluation early
+#[must_use]
+pub fn eval_program(program: &ast::Program, env: &Rc<RefCell<Environment>>) -> Object {
+    // An interrupt only applies to the evaluation that was running when it arrived
+    env.borrow().take_interrupt();
+    let mut result = Object::Null;
+
+    for statement in &program.statements {
+        result = eval_statement(statement, env);
+
+        match result {
+            Object::ReturnValue(value) => return *value,
+            Object::Error { .. } => return result,
+            _ => {}
+        }
+    }
+
+    result
+}
+
+/// Like `eval_program`, but leaves `ReturnValue` wrapped so a `return` nested inside
+/// a function body keeps unwinding to the call site
+fn eval_statements(statements: &[ast::Statement], env: &Rc<RefCell<Environment>>) -> Object {
+    let mut result = Object::Null;
+
+    for statement in statements {
+        result = eval_statement(statement, env);
+
+        if matches!(result, Object::ReturnValue(_) | Object::Error { .. }) {
+            return result;
+        }
+    }
+
+    result
+}
+
+fn eval_statement(statement: &ast::Statement, env: &Rc<RefCell<Environment>>) -> Object {
+    match statement {
+        ast::Statement::Expression(_, expression) => eval_expression(expression, env),
+        ast::Statement::Return(_, expression) => {
+            let value = eval_expression(expression, env);
+            if value.is_error() {
+                return value;
+            }
+            Object::ReturnValue(Box::new(value))
+        }
+        ast::Statement::Let(_, ident, expression) => {
+            let value = eval_expression(expression, env);
+            if value.is_error() {
+                return value;
+            }
+            env.borrow_mut().set(&ident.value, value);
+            Object::Null
+        }
+    }
+}
+
+fn eval_expression(expression: &ast::Expression, env: &Rc<RefCell<Environment>>) -> Object {
+    match expression {
+        // Only a lexer in `IntegerMode::BigInt` makes literals too large for an `i64`
+        ast::Expression::Integer(token) => match token.literal().parse::<i64>() {
+            Ok(value) => Object::Integer(value),
+            Err(_) => token.literal().parse::<BigInt>().map_or_else(
+                |_| {
+                    Object::error(
+                        format!("could not parse {} as integer", token.literal()),
+                        token.span,
+                    )
+                },
+                Object::from,
+            ),
+        },
+        ast::Expression::Float(token) => token.literal().parse::<f64>().map_or_else(
+            |_| {
+                Object::error(
+                    format!("could not parse {} as float", token.literal()),
+                    token.span,
+                )
+            },
+            Object::Float,
+        ),
+        ast::Expression::Identifier(token) => {
+            let name = token.literal();
+            env.borrow()
+                .get(name)
+                .or_else(|| builtins::lookup(name))
+                .unwrap_or_else(|| {
+                    Object::error(format!("identifier not found: {}", name), token.span)
+                })
+        }
+        ast::Expression::Boolean(token) => Object::Boolean(token.kind == TokenKind::True),
+        ast::Expression::StringLiteral(token) => Object::String(token.literal().to_owned()),
+        ast::Expression::Prefix { token, right } => {
+            let right = eval_expression(right, env);
+            if right.is_error() {
+                return right;
+            }
+            let mode = env.borrow().integer_mode();
+            eval_prefix_expression(token.kind, right, mode, expression.span())
+        }
+        ast::Expression::Infix { token, left, right } => {
+            let left = eval_expression(left, env);
+            if left.is_error() {
+                return left;
+            }
+            let right = eval_expression(right, env);
+            if right.is_error() {
+                return right;
+            }
+            let mode = env.borrow().integer_mode();
+            eval_infix_expression(token.kind, left, right, mode, expression.span())
+        }
+        ast::Expression::If {
+            condition,
+            consequence,
+            alternative,
+            ..
+        } => {
+            let condition = eval_expression(condition, env);
+            if condition.is_error() {
+                return condition;
+            }
+
+            if condition.is_truthy() {
+                eval_statements(&consequence.statements, env)
+            } else if let Some(alternative) = alternative {
+                eval_statements(&alternative.statements, env)
+            } else {
+                Object::Null
+            }
+        }
+        ast::Expression::FunctionLiteral { params, body, .. } => {
+            Object::Function(object::Function {
+                parameters: params.clone(),
+                body: body.clone(),
+                env: Rc::clone(env),
+            })
+        }
+        ast::Expression::Call { function, args, .. } => {
+            let function = eval_expression(function, env);
+            if function.is_error() {
+                return function;
+            }
+
+            match eval_expressions(args, env) {
+                Ok(args) => apply_function(function, &args, expression.span()),
+                Err(err) => err,
+            }
+        }
+        ast::Expression::ArrayLiteral { elements, .. } => {
+            eval_expressions(elements, env).map_or_else(|err| err, Object::Array)
+        }
+        ast::Expression::HashLiteral { pairs, .. } => eval_hash_literal(pairs, env),
+        ast::Expression::Index { left, index, .. } => {
+            let left = eval_expression(left, env);
+            if left.is_error() {
+                return left;
+            }
+            let index = eval_expression(index, env);
+            if index.is_error() {
+                return index;
+            }
+            eval_index_expression(left, index, expression.span())
+        }
+    }
+}
+
+/// Evaluates `expressions` left to right, stopping at the first error
+fn eval_expressions(
+    expressions: &[ast::Expression],
+    env: &Rc<RefCell<Environment>>,
+) -> Result<Vec<Object>, Object> {
+    let mut values = Vec::with_capacity(expressions.len());
+
+    for expression in expressions {
+        let value = eval_expression(expression, env);
+        if value.is_error() {
+            return Err(value);
+        }
+        values.push(value);
+    }
+
+    Ok(values)
+}
+
+pub(crate) fn eval_prefix_expression(
+    operator: TokenKind,
+    right: Object,
+    mode: IntegerMode,
+    span: token::Span,
+) -> Object {
+    match (operator, right) {
+        (TokenKind::Bang, right) => Object::Boolean(!right.is_truthy()),
+        (TokenKind::Minus, Object::Integer(value)) => match (value.checked_neg(), mode) {
+            (Some(value), _) => Object::Integer(value),
+            (None, IntegerMode::BigInt) => Object::from(-BigInt::from(value)),
+            (None, IntegerMode::Checked) => {
+                Object::error(format!("integer overflow: -({})", value), span)
+            }
+        },
+        (TokenKind::Minus, Object::BigInt(value)) => Object::from(-value),
+        (TokenKind::Minus, Object::Float(value)) => Object::Float(-value),
+        (_, right) => Object::error(
+            format!("unknown operator: {}{}", operator, right.type_name()),
+            span,
+        ),
+    }
+}
+
+pub(crate) fn eval_infix_expression(
+    operator: TokenKind,
+    left: Object,
+    right: Object,
+    mode: IntegerMode,
+    span: token::Span,
+) -> Object {
+    match (left, right) {
+        (Object::Integer(left), Object::Integer(right)) => {
+            eval_integer_infix_expression(operator, left, right, mode, span)
+        }
+        (left @ Object::BigInt(_), right @ (Object::Integer(_) | Object::BigInt(_)))
+        | (left @ Object::Integer(_), right @ Object::BigInt(_)) => {
+            eval_bigint_infix_expression(operator, &to_bigint(left), &to_bigint(right), span)
+        }
+        // An integer meeting a float is promoted to a float
+        (
+            left @ Object::Float(_),
+            right @ (Object::Integer(_) | Object::BigInt(_) | Object::Float(_)),
+        )
+        | (left @ (Object::Integer(_) | Object::BigInt(_)), right @ Object::Float(_)) => {
+            match (left.to_float(), right.to_float()) {
+                (Some(left), Some(right)) => {
+                    eval_float_infix_expression(operator, left, right, span)
+                }
+                _ => unreachable!("both operands are numbers"),
+            }
+        }
+        (Object::Boolean(left), Object::Boolean(right)) => match operator {
+            TokenKind::Eq => Object::Boolean(left == right),
+            TokenKind::NotEq => Object::Boolean(left != right),
+            _ => Object::error(
+                format!("unknown operator: BOOLEAN {} BOOLEAN", operator),
+                span,
+            ),
+        },
+        (Object::String(left), Object::String(right)) => {
+            eval_string_infix_expression(operator, &left, &right, span)
+        }
+        (left, right) if left.type_name() != right.type_name() => Object::error(
+            format!(
+                "type mismatch: {} {} {}",
+                left.type_name(),
+                operator,
+                right.type_name()
+            ),
+            span,
+        ),
+        (left, right) => Object::error(
+            format!(
+                "unknown operator: {} {} {}",
+                left.type_name(),
+                operator,
+                right.type_name()
+            ),
+            span,
+        ),
+    }
+}
+
+fn eval_integer_infix_expression(
+    operator: TokenKind,
+    left: i64,
+    right: i64,
+    mode: IntegerMode,
+    span: token::Span,
+) -> Object {
+    let result = match operator {
+        TokenKind::Plus => left.checked_add(right),
+        TokenKind::Minus => left.checked_sub(right),
+        TokenKind::Asterisk => left.checked_mul(right),
+        TokenKind::Slash if right == 0 => {
+            return Object::error("division by zero".to_owned(), span);
+        }
+        // Only `i64::MIN / -1` overflows
+        TokenKind::Slash => left.checked_div(right),
+        TokenKind::Lt => return Object::Boolean(left < right),
+        TokenKind::Gt => return Object::Boolean(left > right),
+        TokenKind::Eq => return Object::Boolean(left == right),
+        TokenKind::NotEq => return Object::Boolean(left != right),
+        _ => {
+            return Object::error(
+                format!("unknown operator: INTEGER {} INTEGER", operator),
+                span,
+            );
+        }
+    };
+
+    match (result, mode) {
+        (Some(value), _) => Object::Integer(value),
+        (None, IntegerMode::BigInt) => {
+            eval_bigint_infix_expression(operator, &left.into(), &right.into(), span)
+        }
+        (None, IntegerMode::Checked) => Object::error(
+            format!("integer overflow: {} {} {}", left, operator, right),
+            span,
+        ),
+    }
+}
+
+/// Arithmetic on integers of either size, giving an ordinary integer whenever the
+/// result fits in one
+fn eval_bigint_infix_expression(
+    operator: TokenKind,
+    left: &BigInt,
+    right: &BigInt,
+    span: token::Span,
+) -> Object {
+    match operator {
+        TokenKind::Plus => Object::from(left + right),
+        TokenKind::Minus => Object::from(left - right),
+        TokenKind::Asterisk => Object::from(left * right),
+        TokenKind::Slash if right.sign() == Sign::NoSign => {
+            Object::error("division by zero".to_owned(), span)
+        }
+        TokenKind::Slash => Object::from(left / right),
+        TokenKind::Lt => Object::Boolean(left < right),
+        TokenKind::Gt => Object::Boolean(left > right),
+        TokenKind::Eq => Object::Boolean(left == right),
+        TokenKind::NotEq => Object::Boolean(left != right),
+        _ => Object::error(
+            format!("unknown operator: INTEGER {} INTEGER", operator),
+            span,
+        ),
+    }
+}
+
+/// Arithmetic on floats follows IEEE 754, so dividing by zero gives an infinity or
+/// `NaN` rather than an error
+fn eval_float_infix_expression(
+    operator: TokenKind,
+    left: f64,
+    right: f64,
+    span: token::Span,
+) -> Object {
+    match operator {
+        TokenKind::Plus => Object::Float(left + right),
+        TokenKind::Minus => Object::Float(left - right),
+        TokenKind::Asterisk => Object::Float(left * right),
+        TokenKind::Slash => Object::Float(left / right),
+        TokenKind::Lt => Object::Boolean(left < right),
+        TokenKind::Gt => Object::Boolean(left > right),
+        TokenKind::Eq => Object::Boolean(left == right),
+        TokenKind::NotEq => Object::Boolean(left != right),
+        _ => Object::error(format!("unknown operator: FLOAT {} FLOAT", operator), span),
+    }
+}
+
+fn to_bigint(object: Object) -> BigInt {
+    match object {
+        Object::Integer(value) => value.into(),
+        Object::BigInt(value) => value,
+        other => unreachable!("{} is not an integer", other.type_name()),
+    }
+}
+
+fn eval_string_infix_expression(
+    operator: TokenKind,
+    left: &str,
+    right: &str,
+    span: token::Span,
+) -> Object {
+    match operator {
+        TokenKind::Plus => Object::String(format!("{}{}", left, right)),
+        TokenKind::Lt => Object::Boolean(left < right),
+        TokenKind::Gt => Object::Boolean(left > right),
+        TokenKind::Eq => Object::Boolean(left == right),
+        TokenKind::NotEq => Object::Boolean(left != right),
+        _ => Object::error(
+            format!("unknown operator: STRING {} STRING", operator),
+            span,
+        ),
+    }
+}
+
+fn eval_hash_literal(
+    pairs: &[(ast::Expression, ast::Expression)],
+    env: &Rc<RefCell<Environment>>,
+) -> Object {
+    let mut hash = BTreeMap::new();
+
+    for (key_expression, value_expression) in pairs {
+        let key = eval_expression(key_expression, env);
+        if key.is_error() {
+            return key;
+        }
+        let Some(key) = key.hash_key() else {
+            return unusable_hash_key(&key, key_expression.span());
+        };
+
+        let value = eval_expression(value_expression, env);
+        if value.is_error() {
+            return value;
+        }
+
+        hash.insert(key, value);
+    }
+
+    Object::Hash(hash)
+}
+
+pub(crate) fn unusable_hash_key(key: &Object, span: token::Span) -> Object {
+    Object::error(format!("unusable as hash key: {}", key.type_name()), span)
+}
+
+pub(crate) fn eval_index_expression(left: Object, index: Object, span: token::Span) -> Object {
+    match (left, index) {
+        (Object::Array(elements), Object::Integer(index)) => usize::try_from(index)
+            .ok()
+            .and_then(|i| elements.get(i))
+            .cloned()
+            .unwrap_or_else(|| index_out_of_bounds(elements.len(), index, span)),
+        (Object::Array(elements), Object::BigInt(index)) => {
+            index_out_of_bounds(elements.len(), index, span)
+        }
+        (Object::Array(_), index) => Object::error(
+            format!("array index must be an INTEGER, got {}", index.type_name()),
+            span,
+        ),
+        // A missing key is not an error, so `if (h["key"])` can test for one
+        (Object::Hash(pairs), index) => match index.hash_key() {
+            Some(key) => pairs.get(&key).cloned().unwrap_or(Object::Null),
+            None => unusable_hash_key(&index, span),
+        },
+        (left, _) => Object::error(
+            format!("index operator not supported: {}", left.type_name()),
+            span,
+        ),
+    }
+}
+
+fn index_out_of_bounds(len: usize, index: impl fmt::Display, span: token::Span) -> Object {
+    Object::error(
+        format!(
+            "index out of bounds: the length is {} but the index is {}",
+            len, index
+        ),
+        span,
+    )
+}
+
+fn apply_function(function: Object, args: &[Object], span: token::Span) -> Object {
+    match function {
+        Object::Function(function) => {
+            if function.env.borrow().take_interrupt() {
+                return Object::error(String::from("interrupted"), span);
+            }
+
+            if function.parameters.len() != args.len() {
+                return wrong_number_of_arguments(function.parameters.len(), args.len(), span);
+            }
+
+            let mut scope = Environment::new_enclosed(Rc::clone(&function.env));
+            for (parameter, arg) in function.parameters.iter().zip(args) {
+                scope.set(&parameter.value, arg.clone());
+            }
+
+            let depth = CALL_DEPTH.get();
+            if depth >= MAX_CALL_DEPTH {
+                return Object::error("stack overflow".to_owned(), span);
+            }
+
+            let scope = Rc::new(RefCell::new(scope));
+            CALL_DEPTH.set(depth + 1);
+            let result = stacker::maybe_grow(STACK_RED_ZONE, STACK_GROWTH, || {
+                unwrap_return_value(eval_statements(&function.body.statements, &scope))
+            });
+            CALL_DEPTH.set(depth);
+            result
+        }
+        Object::Builtin(builtin) => apply_builtin(&builtin, args, span),
+        other => Object::error(format!("not a function: {}", other.type_name()), span),
+    }
+}
+
+pub(crate) fn apply_builtin(builtin: &Builtin, args: &[Object], span: token::Span) -> Object {
+    match builtin.arity {
+        Some(arity) if arity != args.len() => wrong_number_of_arguments(arity, args.len(), span),
+        _ => (builtin.function)(args).unwrap_or_else(|message| Object::error(message, span)),
+    }
+}
+
+pub(crate) fn wrong_number_of_arguments(expected: usize, got: usize, span: token::Span) -> Object {
+    Object::error(
+        format!(
+            "wrong number of arguments: expected {}, got {}",
+            expected, got
+        ),
+        span,
+    )
+}
+
+fn unwrap_return_value(object: Object) -> Object {
+    match object {
+        Object::ReturnValue(value) => *value,
+        object => object,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer;
+    use crate::object::HashKey;
+    use crate::parser;
+
+    fn test_eval(input: &str) -> Object {
+        let lexer = lexer::Lexer::new(input);
+        let mut parser = parser::Parser::new(lexer);
+        let program = parser.parse_program();
+        assert!(parser.errors.is_empty(), "{:?}", parser.errors);
+        let env = Rc::new(RefCell::new(Environment::new()));
+        eval_program(&program, &env)
+    }
+
+    fn test_eval_bigint(input: &str) -> Object {
+        let lexer = lexer::Lexer::new(input).with_integer_mode(IntegerMode::BigInt);
+        let mut parser = parser::Parser::new(lexer);
+        let program = parser.parse_program();
+        assert!(parser.errors.is_empty(), "{:?}", parser.errors);
+        let env = Environment::with_integer_mode(IntegerMode::BigInt);
+        eval_program(&program, &Rc::new(RefCell::new(env)))
+    }
+
+    fn error_message(object: Object) -> String {
+        match object {
+            Object::Error { message, .. } => message,
+            object => panic!("expected an error, got {:?}", object),
+        }
+    }
+
+    #[test]
+    fn test_eval_integer_expression() {
+        let cases = [
+            ("5", 5),
+            ("-10", -10),
+            ("5 + 5 + 5 + 5 - 10", 10),
+            ("2 * 2 * 2 * 2 * 2", 32),
+            ("-50 + 100 + -50", 0),
+            ("20 + 2 * -10", 0),
+            ("50 / 2 * 2 + 10", 60),
+            ("3 * (3 * 3) + 10", 37),
+            ("(5 + 10 * 2 + 15 / 3) * 2 + -10", 50),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(
+                Object::Integer(expected),
+                test_eval(input),
+                "input: {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_eval_float_expression() {
+        let cases = [
+            ("3.14", "3.14"),
+            ("1e-9", "1e-9"),
+            ("-2.5", "-2.5"),
+            ("0.1 + 0.2", "0.30000000000000004"),
+            ("1 + 2.5", "3.5"),
+            ("2.0 * 3", "6.0"),
+            ("7 / 2", "3"),
+            ("7 / 2.0", "3.5"),
+            ("1_000 * 1.5", "1500.0"),
+            ("1.0 / 0", "inf"),
+            ("1 == 1.0", "true"),
+            ("2 < 2.5", "true"),
+            ("0xff + 0o7 + 0b1", "263"),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(expected, test_eval(input).to_string(), "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_bigint_mode() {
+        let cases = [
+            ("9223372036854775807 + 1", "9223372036854775808"),
+            (
+                "4294967296 * 4294967296 * 4294967296",
+                "79228162514264337593543950336",
+            ),
+            ("-(-9223372036854775807 - 1)", "9223372036854775808"),
+            ("9223372036854775807 + 1 > 9223372036854775807", "true"),
+            (
+                "(9223372036854775807 + 1) / 0",
+                "ERROR: 1:2: division by zero",
+            ),
+            (
+                "let f = fn(n) { if (n < 2) { 1 } else { n * f(n - 1) } }; f(25)",
+                "15511210043330985984000000",
+            ),
+            (
+                r#"{9223372036854775807 + 1: "big"}[9223372036854775807 + 1]"#,
+                "big",
+            ),
+            ("(9223372036854775807 + 1) * 0.5", "4.611686018427388e18"),
+            ("99999999999999999999 + 1", "100000000000000000000"),
+            ("-0x1_0000_0000_0000_0000", "-18446744073709551616"),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(
+                expected,
+                test_eval_bigint(input).to_string(),
+                "input: {}",
+                input
+            );
+        }
+
+        // Results that fit in an `i64` again are ordinary integers
+        assert_eq!(
+            Object::Integer(9_223_372_036_854_775_807),
+            test_eval_bigint("9223372036854775807 + 1 - 1")
+        );
+        assert_eq!(
+            Object::Integer(-9_223_372_036_854_775_808),
+            test_eval_bigint("-9223372036854775808")
+        );
+        assert_eq!(
+            "type mismatch: INTEGER + STRING",
+            error_message(test_eval_bigint(r#"9223372036854775807 * 2 + "a""#))
+        );
+    }
+
+    #[test]
+    fn test_eval_boolean_expression() {
+        let cases = [
+            ("1 < 2", true),
+            ("1 > 2", false),
+            ("1 == 1", true),
+            ("1 != 1", false),
+            ("(1 < 2) == (2 > 1)", true),
+            ("(1 > 2) != (2 > 1)", true),
+            ("!5", false),
+            ("!!5", true),
+            ("!(1 > 2)", true),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(
+                Object::Boolean(expected),
+                test_eval(input),
+                "input: {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_return_statements() {
+        let cases = [
+            ("return 10;", 10),
+            ("return 10; 9;", 10),
+            ("return 2 * 5; 9;", 10),
+            ("9; return 2 * 5; 9;", 10),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(
+                Object::Integer(expected),
+                test_eval(input),
+                "input: {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_error_handling() {
+        let cases = [
+            ("5 + (1 < 2);", "type mismatch: INTEGER + BOOLEAN"),
+            ("5 + (1 < 2); 5;", "type mismatch: INTEGER + BOOLEAN"),
+            ("-(1 < 2)", "unknown operator: -BOOLEAN"),
+            ("(1 < 2) + (2 < 3)", "unknown operator: BOOLEAN + BOOLEAN"),
+            ("foobar", "identifier not found: foobar"),
+            ("10 / (5 - 5)", "division by zero"),
+            ("5(1)", "not a function: INTEGER"),
+            (r#""a" - "b""#, "unknown operator: STRING - STRING"),
+            (r#""a" + 1"#, "type mismatch: STRING + INTEGER"),
+            (
+                "[1, 2, 3][3]",
+                "index out of bounds: the length is 3 but the index is 3",
+            ),
+            (
+                "[1, 2, 3][-1]",
+                "index out of bounds: the length is 3 but the index is -1",
+            ),
+            ("[1][true]", "array index must be an INTEGER, got BOOLEAN"),
+            ("1[0]", "index operator not supported: INTEGER"),
+            (
+                r#"{"name": "monkey"}[fn(x) { x }]"#,
+                "unusable as hash key: FUNCTION",
+            ),
+            ("{[1]: 2}", "unusable as hash key: ARRAY"),
+            ("{1.5: 2}", "unusable as hash key: FLOAT"),
+            ("1.5 + true", "type mismatch: FLOAT + BOOLEAN"),
+            (r#""a" * 2.0"#, "type mismatch: STRING * FLOAT"),
+            (
+                "9223372036854775807 + 1",
+                "integer overflow: 9223372036854775807 + 1",
+            ),
+            (
+                "-9223372036854775807 - 2",
+                "integer overflow: -9223372036854775807 - 2",
+            ),
+            (
+                "4294967296 * 4294967296",
+                "integer overflow: 4294967296 * 4294967296",
+            ),
+            (
+                "(-9223372036854775807 - 1) / -1",
+                "integer overflow: -9223372036854775808 / -1",
+            ),
+            (
+                "-(-9223372036854775807 - 1)",
+                "integer overflow: -(-9223372036854775808)",
+            ),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(
+                expected,
+                error_message(test_eval(input)),
+                "input: {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_error_spans() {
+        let input = "let a = 1;\nlet b = a + true;";
+
+        let Object::Error { span, .. } = test_eval(input) else {
+            panic!("expected an error");
+        };
+        assert_eq!((2, 9), (span.line, span.column));
+        assert_eq!("a + true", &input[span.start..span.end]);
+    }
+
+    #[test]
+    fn test_let_statements() {
+        let cases = [
+            ("let a = 5; a;", 5),
+            ("let a = 5 * 5; a;", 25),
+            ("let a = 5; let b = a; b;", 5),
+            ("let a = 5; let b = a; let c = a + b + 5; c;", 15),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(
+                Object::Integer(expected),
+                test_eval(input),
+                "input: {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_boolean_literals() {
+        let cases = [
+            ("true", true),
+            ("false", false),
+            ("true == true", true),
+            ("true != false", true),
+            ("(1 < 2) == true", true),
+            ("!true", false),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(
+                Object::Boolean(expected),
+                test_eval(input),
+                "input: {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_strings() {
+        let cases = [
+            (r#""hello world""#, Object::String("hello world".to_owned())),
+            (
+                r#""hello" + " " + "world""#,
+                Object::String("hello world".to_owned()),
+            ),
+            (
+                r#"let greet = fn(name) { "hi " + name }; greet("bob")"#,
+                Object::String("hi bob".to_owned()),
+            ),
+            (r#""a" == "a""#, Object::Boolean(true)),
+            (r#""a" != "a""#, Object::Boolean(false)),
+            (r#""a" < "b""#, Object::Boolean(true)),
+            (r#""abc" > "abd""#, Object::Boolean(false)),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(expected, test_eval(input), "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_array_literals() {
+        assert_eq!(
+            Object::Array(vec![
+                Object::Integer(1),
+                Object::Integer(4),
+                Object::Integer(6)
+            ]),
+            test_eval("[1, 2 * 2, 3 + 3]")
+        );
+        assert_eq!(Object::Array(Vec::new()), test_eval("[]"));
+    }
+
+    #[test]
+    fn test_array_index_expressions() {
+        let cases = [
+            ("[1, 2, 3][0]", 1),
+            ("[1, 2, 3][1 + 1]", 3),
+            ("let i = 0; [1][i]", 1),
+            ("let myArray = [1, 2, 3]; myArray[2]", 3),
+            (
+                "let myArray = [1, 2, 3]; myArray[0] + myArray[1] + myArray[2];",
+                6,
+            ),
+            ("[[1, 2], [3, 4]][1][0]", 3),
+            ("let first = fn(a) { a[0] }; first([5, 6])", 5),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(
+                Object::Integer(expected),
+                test_eval(input),
+                "input: {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_hash_literals() {
+        let input = r#"
+            let two = "two";
+            {
+                "one": 10 - 9,
+                two: 1 + 1,
+                "thr" + "ee": 6 / 2,
+                4: 4,
+                true: 5,
+                false: 6
+            }
+        "#;
+
+        let expected = BTreeMap::from([
+            (HashKey::String("one".to_owned()), Object::Integer(1)),
+            (HashKey::String("two".to_owned()), Object::Integer(2)),
+            (HashKey::String("three".to_owned()), Object::Integer(3)),
+            (HashKey::Integer(4), Object::Integer(4)),
+            (HashKey::Boolean(true), Object::Integer(5)),
+            (HashKey::Boolean(false), Object::Integer(6)),
+        ]);
+
+        assert_eq!(Object::Hash(expected), test_eval(input));
+    }
+
+    #[test]
+    fn test_hash_index_expressions() {
+        let cases = [
+            (r#"{"foo": 5}["foo"]"#, Object::Integer(5)),
+            (r#"{"foo": 5}["bar"]"#, Object::Null),
+            (r#"let key = "foo"; {"foo": 5}[key]"#, Object::Integer(5)),
+            (r#"{}["foo"]"#, Object::Null),
+            ("{5: 5}[5]", Object::Integer(5)),
+            ("{true: 5}[true]", Object::Integer(5)),
+            ("{false: 5}[false]", Object::Integer(5)),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(expected, test_eval(input), "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_builtin_functions() {
+        let array =
+            |values: &[i64]| Object::Array(values.iter().copied().map(Object::Integer).collect());
+
+        let cases = [
+            (r#"len("")"#, Object::Integer(0)),
+            (r#"len("four")"#, Object::Integer(4)),
+            (r#"len("héllo")"#, Object::Integer(5)),
+            ("len([1, 2, 3])", Object::Integer(3)),
+            (r#"len({"a": 1})"#, Object::Integer(1)),
+            ("first([1, 2, 3])", Object::Integer(1)),
+            ("first([])", Object::Null),
+            ("last([1, 2, 3])", Object::Integer(3)),
+            ("last([])", Object::Null),
+            ("rest([1, 2, 3])", array(&[2, 3])),
+            ("rest([1])", array(&[])),
+            ("rest([])", Object::Null),
+            ("push([], 1)", array(&[1])),
+            ("let a = [1]; push(a, 2); a", array(&[1])),
+            (r#"puts("hello", 1)"#, Object::Null),
+            ("let len = fn(x) { 42 }; len([1])", Object::Integer(42)),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(expected, test_eval(input), "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_builtin_function_errors() {
+        let cases = [
+            ("len(1)", "argument to `len` not supported, got INTEGER"),
+            (
+                r#"len("one", "two")"#,
+                "wrong number of arguments: expected 1, got 2",
+            ),
+            ("first(1)", "argument to `first` must be ARRAY, got INTEGER"),
+            (
+                r#"last("a")"#,
+                "argument to `last` must be ARRAY, got STRING",
+            ),
+            (
+                "rest(true)",
+                "argument to `rest` must be ARRAY, got BOOLEAN",
+            ),
+            (
+                "push(1, 1)",
+                "argument to `push` must be ARRAY, got INTEGER",
+            ),
+            ("push([])", "wrong number of arguments: expected 2, got 1"),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(
+                expected,
+                error_message(test_eval(input)),
+                "input: {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_if_else_expressions() {
+        let cases = [
+            ("if (true) { 10 }", Object::Integer(10)),
+            ("if (false) { 10 }", Object::Null),
+            ("if (1) { 10 }", Object::Integer(10)),
+            ("if (1 < 2) { 10 } else { 20 }", Object::Integer(10)),
+            ("if (1 > 2) { 10 } else { 20 }", Object::Integer(20)),
+            (
+                "if (10 > 1) { if (10 > 1) { return 10; } return 1; }",
+                Object::Integer(10),
+            ),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(expected, test_eval(input), "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_function_application() {
+        let cases = [
+            ("let identity = fn(x) { x; }; identity(5);", 5),
+            ("let identity = fn(x) { return x; }; identity(5);", 5),
+            ("let double = fn(x) { x * 2; }; double(5);", 10),
+            ("let add = fn(x, y) { x + y; }; add(5 + 5, add(5, 5));", 20),
+            ("fn(x) { x; }(5)", 5),
+            (
+                "let fact = fn(n) { if (n < 2) { return 1; } n * fact(n - 1) }; fact(5);",
+                120,
+            ),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(
+                Object::Integer(expected),
+                test_eval(input),
+                "input: {}",
+                input
+            );
+        }
+
+        assert_eq!(
+            "wrong number of arguments: expected 2, got 1",
+            error_message(test_eval("let add = fn(x, y) { x + y; }; add(1);"))
+        );
+    }
+
+    #[test]
+    fn test_closures() {
+        let input = r#"
+        let newAdder = fn(x) { fn(y) { x + y } };
+        let addTwo = newAdder(2);
+        let x = 100;
+        addTwo(3);
+        "#;
+
+        assert_eq!(Object::Integer(5), test_eval(input));
+        assert_eq!(
+            "identifier not found: y",
+            error_message(test_eval("let f = fn(y) { y }; f(1); y"))
+        );
+    }
+
+    #[test]
+    fn test_deep_recursion() {
+        let input = "let f = fn(n) { if (n == 0) { 0 } else { 1 + f(n - 1) } }; f(9000)";
+
+        assert_eq!(Object::Integer(9000), test_eval(input));
+    }
+
+    #[test]
+    fn test_infinite_recursion() {
+        assert_eq!(
+            "stack overflow",
+            error_message(test_eval("let g = fn(n) { g(n + 1) }; g(0)"))
+        );
+
+        // The calls are unwound after the error, so later ones start from the top
+        assert_eq!(0, CALL_DEPTH.get());
+    }
+}
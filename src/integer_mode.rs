@@ -0,0 +1,12 @@
+/// What integer arithmetic does when its result does not fit in an `i64`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IntegerMode {
+    /// Overflow is a runtime error, and so is an integer literal too big for an
+    /// `i64`.  The minus sign is not part of a literal, so `i64::MIN` has to be
+    /// written as `-9223372036854775807 - 1`
+    #[default]
+    Checked,
+    /// The result is promoted to an `Object::BigInt`, and goes back to an ordinary
+    /// integer once a later result fits again
+    BigInt,
+}
@@ -0,0 +1,314 @@
+use core::fmt;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::builtins::Builtin;
+use crate::diagnostic::Diagnostic;
+use crate::environment::Environment;
+use crate::evaluator::{self, IntegerMode};
+use crate::interrupt::InterruptHandle;
+use crate::lexer;
+use crate::object::Object;
+use crate::parser;
+
+/// Why `Interpreter::eval_str` failed.  The diagnostics point into the source that
+/// was evaluated, so `Diagnostic::render` can show them against it
+#[derive(Debug)]
+pub enum Error {
+    /// The source did not parse, and nothing was evaluated
+    Parse(Vec<Diagnostic>),
+    /// Evaluation raised a runtime error.  Bindings made before the error are kept
+    Runtime(Diagnostic),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Parse(errors) => {
+                let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
+                write!(f, "{}", errors.join("\n"))
+            }
+            Self::Runtime(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+/// Runs Monkey code on behalf of a host program.  Every call to `eval_str` shares
+/// one global scope, so a script can be loaded once and its bindings read or called
+/// afterwards
+///
+/// A function holds on to the scope it was defined in, and that scope to the
+/// function if it bound it with `let`.  Dropping the interpreter clears the global
+/// scope to free its functions, but the scopes of function calls that define
+/// functions of their own are never freed, so a host that runs such code for long
+/// should make a new interpreter from time to time
+#[derive(Debug, Default)]
+pub struct Interpreter {
+    globals: Rc<RefCell<Environment>>,
+}
+
+impl Interpreter {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An interpreter whose integer arithmetic overflows as `integer_mode` says,
+    /// rather than raising a runtime error
+    #[must_use]
+    pub fn with_integer_mode(integer_mode: IntegerMode) -> Self {
+        Self {
+            globals: Rc::new(RefCell::new(Environment::with_integer_mode(integer_mode))),
+        }
+    }
+
+    /// Parses and evaluates `source` in the global scope, returning the value of its
+    /// last statement
+    ///
+    /// # Errors
+    /// Returns every parse error if `source` does not parse, or the runtime error
+    /// that stopped evaluation
+    pub fn eval_str(&mut self, source: &str) -> Result<Object, Error> {
+        let integer_mode = self.globals.borrow().integer_mode();
+        let lexer = lexer::Lexer::new(source).with_integer_mode(integer_mode);
+        let mut parser = parser::Parser::new(lexer);
+        let program = parser.parse_program();
+
+        if !parser.errors.is_empty() {
+            return Err(Error::Parse(parser.errors));
+        }
+
+        let result = evaluator::eval_program(&program, &self.globals);
+        match result.to_diagnostic() {
+            Some(err) => Err(Error::Runtime(err)),
+            None => Ok(result),
+        }
+    }
+
+    /// Binds `name` in the global scope, replacing any earlier binding
+    pub fn set_global(&mut self, name: &str, value: impl Into<Object>) {
+        self.globals.borrow_mut().set(name, value.into());
+    }
+
+    #[must_use]
+    pub fn get_global(&self, name: &str) -> Option<Object> {
+        self.globals.borrow().get(name)
+    }
+
+    /// A handle that stops this interpreter's evaluation in progress, e.g. from
+    /// another thread or a signal handler
+    #[must_use]
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.globals.borrow().interrupt_handle()
+    }
+
+    /// Makes a Rust closure callable from Monkey as `name`.  The closure receives
+    /// the arguments however many there are, and an `Err` from it becomes a runtime
+    /// error at the call site
+    pub fn register_native_fn<F, T, E>(&mut self, name: &str, function: F)
+    where
+        F: Fn(&[Object]) -> Result<T, E> + 'static,
+        T: Into<Object>,
+        E: fmt::Display,
+    {
+        self.register(name, None, function);
+    }
+
+    /// Like `register_native_fn`, but a call with other than `arity` arguments is a
+    /// runtime error, so the closure can index the arguments it expects
+    pub fn register_native_fn_with_arity<F, T, E>(&mut self, name: &str, arity: usize, function: F)
+    where
+        F: Fn(&[Object]) -> Result<T, E> + 'static,
+        T: Into<Object>,
+        E: fmt::Display,
+    {
+        self.register(name, Some(arity), function);
+    }
+
+    fn register<F, T, E>(&mut self, name: &str, arity: Option<usize>, function: F)
+    where
+        F: Fn(&[Object]) -> Result<T, E> + 'static,
+        T: Into<Object>,
+        E: fmt::Display,
+    {
+        let builtin = Builtin::new(name, arity, move |args| {
+            function(args)
+                .map(Into::into)
+                .map_err(|err| err.to_string())
+        });
+        self.set_global(name, Object::Builtin(builtin));
+    }
+}
+
+impl Drop for Interpreter {
+    fn drop(&mut self) {
+        self.globals.borrow_mut().clear();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::diagnostic;
+    use crate::object::TypeError;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+
+    #[test]
+    fn test_globals_persist_between_evals() {
+        let mut interpreter = Interpreter::new();
+        interpreter.eval_str("let x = 5;").unwrap();
+        interpreter.set_global("y", 10);
+
+        assert_eq!(Object::Integer(15), interpreter.eval_str("x + y").unwrap());
+        assert_eq!(Some(Object::Integer(5)), interpreter.get_global("x"));
+        assert_eq!(None, interpreter.get_global("z"));
+    }
+
+    #[test]
+    fn test_eval_str_errors() {
+        let mut interpreter = Interpreter::new();
+
+        match interpreter.eval_str("let = 1; let y 2;") {
+            Err(Error::Parse(errors)) => assert_eq!(2, errors.len()),
+            other => panic!("expected parse errors, got {:?}", other),
+        }
+
+        match interpreter.eval_str("let a = 1; a + true") {
+            Err(Error::Runtime(err)) => {
+                assert_eq!(diagnostic::RUNTIME_ERROR, err.code);
+                assert_eq!("type mismatch: INTEGER + BOOLEAN", err.message);
+            }
+            other => panic!("expected a runtime error, got {:?}", other),
+        }
+        assert_eq!(Some(Object::Integer(1)), interpreter.get_global("a"));
+    }
+
+    #[test]
+    fn test_native_functions() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register_native_fn_with_arity("join", 1, |args: &[Object]| {
+            let parts: Vec<String> = Vec::try_from(args[0].clone())?;
+            Ok::<_, TypeError>(parts.join(", "))
+        });
+
+        assert_eq!(
+            Object::from("a, b"),
+            interpreter.eval_str(r#"join(["a", "b"])"#).unwrap()
+        );
+
+        match interpreter.eval_str(r#"join(["a", 1])"#) {
+            Err(Error::Runtime(err)) => assert_eq!("expected STRING, got INTEGER", err.message),
+            other => panic!("expected a runtime error, got {:?}", other),
+        }
+
+        match interpreter.eval_str("join()") {
+            Err(Error::Runtime(err)) => {
+                assert_eq!("wrong number of arguments: expected 1, got 0", err.message);
+            }
+            other => panic!("expected a runtime error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_native_functions_without_arity() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register_native_fn("count", |args: &[Object]| {
+            i64::try_from(args.len()).map_err(|err| err.to_string())
+        });
+
+        assert_eq!(
+            Object::Integer(3),
+            interpreter.eval_str("count() + count(1, 2, 3)").unwrap()
+        );
+    }
+
+    #[test]
+    fn test_interrupt() {
+        let mut interpreter = Interpreter::new();
+        let mut other = Interpreter::new();
+        let program = "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } };";
+        interpreter.eval_str(program).unwrap();
+        other.eval_str(program).unwrap();
+
+        // An interrupt that arrives before evaluation starts is dropped, so keep
+        // sending them until one lands
+        let interrupt = interpreter.interrupt_handle();
+        let done = Arc::new(AtomicBool::new(false));
+        let interrupter = std::thread::spawn({
+            let done = Arc::clone(&done);
+            move || {
+                while !done.load(Ordering::Relaxed) {
+                    std::thread::sleep(std::time::Duration::from_millis(10));
+                    interrupt.interrupt();
+                }
+            }
+        });
+
+        let result = interpreter.eval_str("fib(100)");
+        done.store(true, Ordering::Relaxed);
+        interrupter.join().unwrap();
+        match result {
+            Err(Error::Runtime(err)) => assert_eq!("interrupted", err.message),
+            other => panic!("expected an interrupt, got {:?}", other),
+        }
+
+        // Only the interpreter the handle came from stops
+        interpreter.interrupt_handle().interrupt();
+        assert_eq!(Object::Integer(55), other.eval_str("fib(10)").unwrap());
+    }
+
+    #[test]
+    fn test_drop_frees_global_functions() {
+        let mut interpreter = Interpreter::new();
+        let Object::Function(function) = interpreter.eval_str("let f = fn() { f }; f").unwrap()
+        else {
+            panic!("expected a function");
+        };
+
+        drop(interpreter);
+        assert_eq!(1, Rc::strong_count(&function.env));
+        assert_eq!(0, function.env.borrow().bindings().count());
+    }
+
+    #[test]
+    fn test_native_functions_are_not_builtins() {
+        let mut interpreter = Interpreter::new();
+        interpreter.register_native_fn("len", |_: &[Object]| Ok::<_, TypeError>(0));
+
+        let native = interpreter.get_global("len").unwrap();
+        assert_ne!(crate::builtins::lookup("len").unwrap(), native);
+        assert_eq!(native, interpreter.get_global("len").unwrap());
+        assert_eq!(
+            crate::builtins::lookup("len"),
+            crate::builtins::lookup("len")
+        );
+    }
+
+    #[test]
+    fn test_conversions() {
+        let mut interpreter = Interpreter::new();
+        interpreter.set_global("names", vec!["a", "b"]);
+        interpreter.set_global("limits", HashMap::from([("max".to_owned(), 3)]));
+        interpreter.set_global("missing", None::<i64>);
+
+        let config = interpreter
+            .eval_str(r#"{"count": len(names), "max": limits["max"], "none": missing}"#)
+            .unwrap();
+        let config: HashMap<String, Object> = HashMap::try_from(config).unwrap();
+
+        assert_eq!(Ok(2), i64::try_from(config["count"].clone()));
+        assert_eq!(Ok(3), i64::try_from(config["max"].clone()));
+        assert_eq!(Object::Null, config["none"]);
+        assert_eq!(
+            Err(TypeError {
+                expected: "BOOLEAN",
+                found: "INTEGER"
+            }),
+            bool::try_from(Object::Integer(1))
+        );
+    }
+}
@@ -0,0 +1,21 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// Stops the evaluation in progress from another thread or a signal handler.  An
+/// environment and every scope nested in it share one handle, which is checked on
+/// every function call, the only way a Monkey program can run for long
+#[derive(Debug, Clone, Default)]
+pub struct InterruptHandle(Arc<AtomicBool>);
+
+impl InterruptHandle {
+    /// Asks the evaluation in progress to stop with an "interrupted" error.  Only
+    /// stores to an atomic, so it is safe to call from a signal handler
+    pub fn interrupt(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Clears a pending interrupt and returns whether there was one
+    pub(crate) fn take(&self) -> bool {
+        self.0.swap(false, Ordering::Relaxed)
+    }
+}
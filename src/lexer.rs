@@ -1,34 +1,48 @@
-use crate::token;
+use num_bigint::BigInt;
+
+use crate::diagnostic::{self, Diagnostic};
+use crate::integer_mode::IntegerMode;
+use crate::token::{self, TokenKind};
 
 #[derive(Debug)]
-pub struct Lexer {
+pub struct Lexer<'a> {
     /// The input to the lexer. Can be a string or file
-    input: Vec<char>,
+    input: &'a str,
     /// The current character the lexer is lexing
     char: char,
-    /// The current position within the input that the lexer is lexing
+    /// The byte offset of the current character within the input, which is what
+    /// spans are measured in
     position: usize,
-    /// The read position is current position + 1.  This is always looking ahead to
-    /// catch two-character symbols
+    /// The byte offset of the character after the current one.  This is always
+    /// looking ahead to catch two-character symbols
     read_position: usize,
+    /// Added to every byte offset in a span, for input that is one piece of a larger
+    /// source
+    base: usize,
+    /// The 1-based line of the current character
+    line: usize,
+    /// The 1-based column of the current character, counted in characters
+    column: usize,
     /// The end of file boolean.  Will be set to true once the lexer determines we are
     /// at the end of the file
     eof: bool,
+    /// Whether an integer literal too large for an `i64` is an error
+    integer_mode: IntegerMode,
 }
 
-impl Lexer {
+impl<'a> Lexer<'a> {
     #[must_use]
-    pub fn new(input: &str) -> Self {
-        // Collect all characters into a vector
-        let input_as_chars: Vec<char> = input.chars().collect();
-
-        // Construct the lexer
+    pub fn new(input: &'a str) -> Self {
         let mut me = Self {
-            input: input_as_chars,
-            char: '0',
+            input,
+            char: '\0',
             position: 0,
             read_position: 0,
+            base: 0,
+            line: 1,
+            column: 0,
             eof: false,
+            integer_mode: IntegerMode::default(),
         };
 
         me.read_character();
@@ -36,72 +50,119 @@ impl Lexer {
         me
     }
 
+    /// Creates a lexer whose spans start at byte `offset` rather than 0, while lines
+    /// and columns still count from the start of `input`.  Used by the REPL, which
+    /// lexes each entry on its own but gives every one a separate range of offsets
+    #[must_use]
+    pub fn new_at(input: &'a str, offset: usize) -> Self {
+        Self {
+            base: offset,
+            ..Self::new(input)
+        }
+    }
+
+    /// A lexer that, in `IntegerMode::BigInt`, accepts integer literals of any size
+    #[must_use]
+    pub const fn with_integer_mode(mut self, integer_mode: IntegerMode) -> Self {
+        self.integer_mode = integer_mode;
+        self
+    }
+
     /// # Errors
-    /// Something errs prolly
-    pub fn next_token(&mut self) -> Result<token::Token, anyhow::Error> {
+    /// Returns an error if a number literal is malformed or does not fit in its type
+    pub fn next_token(&mut self) -> Result<token::Token, Diagnostic> {
+        // Consume any whitespace leading up to next identifier
+        self.eat_whitespace();
+
+        let start = self.current_span();
+        let token = self.read_token(start)?;
+
+        Ok(token.with_span(token::Span {
+            end: self.offset(),
+            ..start
+        }))
+    }
+
+    fn read_token(&mut self, start: token::Span) -> Result<token::Token, Diagnostic> {
         if self.eof {
-            Ok(token::Token::new(token::EOF, ""))
+            Ok(token::Token::new(TokenKind::Eof, ""))
+        // If current identifier matches for a two character symbol
+        } else if let Some(kind) = self.two_character_symbol() {
+            self.read_character();
+            self.read_character();
+            Ok(token::Token::new(kind, ""))
+        // Otherwise, if identifier matches for a one character symbol
+        } else if let Some(kind) = self.symbol() {
+            self.read_character();
+            Ok(token::Token::new(kind, ""))
+        // Otherwise, if a string starts here
+        } else if self.char == '"' {
+            let string = self.read_string(start)?;
+            Ok(token::Token::new(TokenKind::String, &string))
+        // Otherwise, if an identifier starts here
+        } else if is_identifier_start(self.char) {
+            let word = self.read_identifier();
+            let kind = TokenKind::keyword(&word).unwrap_or(TokenKind::Ident);
+            Ok(token::Token::new(kind, &word))
+        // Otherwise, if identifier is a number
+        } else if self.char.is_ascii_digit() {
+            self.read_number(start)
+        // Otherwise, the identifier is illegal
         } else {
-            // Consume any whitespace leading up to next identifier
-            self.eat_whitespace();
-
-            // If current identifier matches for a two character symbol
-            if self.is_two_character_symbol() {
-                let symbol = self.consume_two_character_symbol();
-                self.read_character();
-                Ok(token::Token::try_from(symbol)?)
-            // Otherwise, if identifier matches for a one character symbol
-            } else if self.is_symbol() {
-                let symbol = self.char.to_string();
-                self.read_character();
-                Ok(token::Token::try_from(symbol)?)
-            // Otherwise, if identifier is alphabetic
-            } else if self.char.is_alphabetic() {
-                let symbol = self.read_identifier();
-                Ok(token::Token::try_from(symbol)?)
-            // Otherwise, if identifier is a number
-            } else if self.char.is_ascii_digit() {
-                let symbol = self.read_number()?;
-                Ok(token::Token::try_from(symbol)?)
-            // Otherwise, if EOF was discovered while identifing the token
-            } else if self.eof {
-                Ok(token::Token::new(token::EOF, ""))
-            // Otherwise, the identifier is illegal
-            } else {
-                self.read_character();
-                Ok(token::Token::new(token::ILLEGAL, &self.char.to_string()))
-            }
+            let illegal = self.char.to_string();
+            self.read_character();
+            Ok(token::Token::new(TokenKind::Illegal, &illegal))
         }
     }
 
-    fn is_two_character_symbol(&self) -> bool {
-        self.peek_character().map_or(false, |next_char| {
-            let candidate_symbol: String = format!("{}{}", self.char, next_char);
-            matches!(candidate_symbol.as_str(), "==" | "!=")
-        })
+    /// An empty span at the current character
+    const fn current_span(&self) -> token::Span {
+        token::Span {
+            start: self.offset(),
+            end: self.offset(),
+            line: self.line,
+            column: self.column,
+        }
     }
 
-    fn consume_two_character_symbol(&mut self) -> String {
-        let current_char = self.char;
-        self.read_character();
-        let next_char = self.char;
-        let symbol = format!("{current_char}{next_char}");
+    /// The offset of the current character as it appears in spans
+    const fn offset(&self) -> usize {
+        self.base + self.position
+    }
 
-        symbol
+    fn two_character_symbol(&self) -> Option<TokenKind> {
+        match (self.char, self.peek_character()?) {
+            ('=', '=') => Some(TokenKind::Eq),
+            ('!', '=') => Some(TokenKind::NotEq),
+            _ => None,
+        }
     }
 
-    fn peek_character(&self) -> Result<char, anyhow::Error> {
-        self.input
-            .get(self.read_position)
-            .map_or_else(|| Err(anyhow::anyhow!("Peek EOF")), |char| Ok(*char))
+    fn peek_character(&self) -> Option<char> {
+        self.input[self.read_position..].chars().next()
     }
 
     fn read_character(&mut self) {
-        if let Some(char) = self.input.get(self.read_position) {
+        if self.eof {
+            return;
+        }
+
+        if self.char == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+
+        if let Some(char) = self.peek_character() {
             self.position = self.read_position;
-            self.read_position += 1;
-            self.char = *char;
+            self.read_position += char.len_utf8();
+            self.char = char;
         } else {
+            // Step past the final character so a trailing identifier or number
+            // still spans up to the end of the input
+            self.position = self.input.len();
+            self.char = '\0';
             self.eof = true;
         }
     }
@@ -115,77 +176,689 @@ impl Lexer {
     fn read_identifier(&mut self) -> String {
         let position = self.position;
 
-        while self.char.is_alphabetic() && !self.eof {
+        while unicode_ident::is_xid_continue(self.char) && !self.eof {
             self.read_character();
         }
 
-        String::from_iter(&self.input[position..self.position])
+        self.input[position..self.position].to_owned()
     }
 
-    fn read_number(&mut self) -> Result<i32, anyhow::Error> {
+    /// Reads an integer or float literal.  Integers may also be written in hex, octal
+    /// or binary after a `0x`, `0o` or `0b` prefix, and the digits of any number may
+    /// be separated by underscores.  An integer's literal is its decimal value and a
+    /// float's is its text without underscores
+    fn read_number(&mut self, start: token::Span) -> Result<token::Token, Diagnostic> {
         let position = self.position;
 
-        while self.char.is_ascii_digit() && !self.eof {
+        let radix = match (self.char, self.peek_character()) {
+            ('0', Some('x')) => 16,
+            ('0', Some('o')) => 8,
+            ('0', Some('b')) => 2,
+            _ => 10,
+        };
+
+        if radix != 10 {
+            // Skip the prefix
+            self.read_character();
+            self.read_character();
+
+            let digits = self.read_digits(radix, start, position)?;
+            self.check_number_end(start, position)?;
+            if digits.is_empty() {
+                return Err(
+                    self.number_error(start, format!("{} has no digits", self.text_from(position)))
+                );
+            }
+
+            let number = self.integer_literal(&digits, radix, start, position)?;
+            return Ok(token::Token::new(TokenKind::Int, &number));
+        }
+
+        let mut number = self.read_digits(10, start, position)?;
+        let mut is_float = false;
+
+        // A dot only starts a fraction when a digit follows it
+        if self.char == '.' && self.peek_character().is_some_and(|c| c.is_ascii_digit()) {
+            self.read_character();
+            number.push('.');
+            number.push_str(&self.read_digits(10, start, position)?);
+            is_float = true;
+        }
+
+        if self.char == 'e' || self.char == 'E' {
             self.read_character();
+            number.push('e');
+            if self.char == '+' || self.char == '-' {
+                number.push(self.char);
+                self.read_character();
+            }
+
+            let exponent = self.read_digits(10, start, position)?;
+            if exponent.is_empty() {
+                return Err(self.number_error(
+                    start,
+                    format!("{} has no digits in its exponent", self.text_from(position)),
+                ));
+            }
+            number.push_str(&exponent);
+            is_float = true;
         }
 
-        let number_string = String::from_iter(&self.input[position..self.position]);
-        let number = number_string.parse::<i32>()?;
+        self.check_number_end(start, position)?;
 
-        Ok(number)
+        if is_float {
+            // Every float literal Rust cannot parse has been rejected above, so only
+            // one too large for an f64 is left
+            match number.parse::<f64>() {
+                Ok(float) if float.is_finite() => Ok(token::Token::new(TokenKind::Float, &number)),
+                _ => Err(self.number_error(
+                    start,
+                    format!(
+                        "could not parse {} as a float: number too large",
+                        self.text_from(position)
+                    ),
+                )),
+            }
+        } else {
+            let integer = self.integer_literal(&number, 10, start, position)?;
+            Ok(token::Token::new(TokenKind::Int, &integer))
+        }
     }
 
-    const fn is_symbol(&self) -> bool {
-        matches!(
-            self.char,
-            '=' | ';' | '(' | ')' | ',' | '+' | '{' | '}' | '!' | '-' | '/' | '*' | '<' | '>'
+    /// The decimal literal of the integer `digits` in `radix`.  One too large for an
+    /// `i64` is an error unless the lexer is in `IntegerMode::BigInt`
+    fn integer_literal(
+        &self,
+        digits: &str,
+        radix: u32,
+        start: token::Span,
+        position: usize,
+    ) -> Result<String, Diagnostic> {
+        let err = match i64::from_str_radix(digits, radix) {
+            Ok(integer) => return Ok(integer.to_string()),
+            Err(err) => err,
+        };
+
+        let integer = match BigInt::parse_bytes(digits.as_bytes(), radix) {
+            Some(integer) if self.integer_mode == IntegerMode::BigInt => {
+                return Ok(integer.to_string());
+            }
+            integer => integer,
+        };
+
+        let err = self.number_error(
+            start,
+            format!(
+                "could not parse {} as an integer: {}",
+                self.text_from(position),
+                err
+            ),
+        );
+        if integer.is_some_and(|integer| -integer == BigInt::from(i64::MIN)) {
+            return Err(err.with_note(
+                "the minus sign is not part of the literal, so write the smallest integer as \
+                 -9223372036854775807 - 1",
+            ));
+        }
+        Err(err)
+    }
+
+    /// Reads digits in `radix`, which may be separated by single underscores, and
+    /// returns them with the underscores removed
+    fn read_digits(
+        &mut self,
+        radix: u32,
+        start: token::Span,
+        position: usize,
+    ) -> Result<String, Diagnostic> {
+        let digits_position = self.position;
+        let mut digits = String::new();
+
+        while (self.char.is_digit(radix) || self.char == '_') && !self.eof {
+            if self.char != '_' {
+                digits.push(self.char);
+            }
+            self.read_character();
+        }
+
+        let text = self.input[digits_position..self.position].to_owned();
+        if text.starts_with('_') || text.ends_with('_') || text.contains("__") {
+            return Err(self
+                .number_error(
+                    start,
+                    format!("misplaced underscore in {}", self.text_from(position)),
+                )
+                .with_note("underscores may only separate two digits"));
+        }
+
+        Ok(digits)
+    }
+
+    /// Rejects a number followed directly by a letter or digit, such as `0xfg` or
+    /// `0b12`, rather than lexing the rest as a separate token
+    fn check_number_end(&mut self, start: token::Span, position: usize) -> Result<(), Diagnostic> {
+        if !unicode_ident::is_xid_continue(self.char) || self.eof {
+            return Ok(());
+        }
+
+        let digit = self.char;
+        while unicode_ident::is_xid_continue(self.char) && !self.eof {
+            self.read_character();
+        }
+
+        Err(self.number_error(
+            start,
+            format!("invalid digit {:?} in {}", digit, self.text_from(position)),
+        ))
+    }
+
+    /// The source text from the character at `position` up to the current one
+    fn text_from(&self, position: usize) -> String {
+        self.input[position..self.position].to_owned()
+    }
+
+    /// An invalid number error spanning from `start` to the current character
+    fn number_error(&self, start: token::Span, message: String) -> Diagnostic {
+        Diagnostic::error(
+            diagnostic::INVALID_NUMBER,
+            message,
+            token::Span {
+                end: self.offset(),
+                ..start
+            },
         )
     }
+
+    /// Reads a string from its opening quote to its closing one and returns its
+    /// contents with escapes resolved.  A bad escape does not stop the string being
+    /// read, so lexing carries on after its closing quote
+    fn read_string(&mut self, start: token::Span) -> Result<String, Diagnostic> {
+        // Skip the opening quote
+        self.read_character();
+
+        let mut string = String::new();
+        let mut error = None;
+
+        loop {
+            if self.eof {
+                return Err(Diagnostic::error(
+                    diagnostic::UNTERMINATED_STRING,
+                    "unterminated string",
+                    token::Span {
+                        end: start.start + 1,
+                        ..start
+                    },
+                )
+                .with_note("the string runs to the end of the input without a closing \""));
+            }
+
+            match self.char {
+                '"' => {
+                    self.read_character();
+                    break;
+                }
+                '\\' => match self.read_escape() {
+                    Ok(char) => string.push(char),
+                    Err(err) => {
+                        error.get_or_insert(err);
+                    }
+                },
+                char => {
+                    string.push(char);
+                    self.read_character();
+                }
+            }
+        }
+
+        error.map_or(Ok(string), Err)
+    }
+
+    /// Reads an escape sequence starting at a backslash
+    fn read_escape(&mut self) -> Result<char, Diagnostic> {
+        let start = self.current_span();
+        let position = self.position;
+
+        // Skip the backslash
+        self.read_character();
+
+        let char = match self.char {
+            'n' => Some('\n'),
+            't' => Some('\t'),
+            '"' => Some('"'),
+            '\\' => Some('\\'),
+            'u' => {
+                self.read_character();
+                return self.read_unicode_escape(start, position);
+            }
+            _ => None,
+        };
+
+        if !self.eof {
+            self.read_character();
+        }
+
+        char.ok_or_else(|| {
+            Diagnostic::error(
+                diagnostic::INVALID_ESCAPE,
+                format!(
+                    "unknown escape sequence {}",
+                    self.input[position..self.position].to_owned()
+                ),
+                token::Span {
+                    end: self.offset(),
+                    ..start
+                },
+            )
+            .with_note("valid escapes are \\n, \\t, \\\", \\\\ and \\u{...}")
+        })
+    }
+
+    /// Reads the `{...}` of a `\u{...}` escape, which holds the hex code of a
+    /// Unicode scalar value
+    fn read_unicode_escape(
+        &mut self,
+        start: token::Span,
+        position: usize,
+    ) -> Result<char, Diagnostic> {
+        let mut hex = String::new();
+        let braced = self.char == '{';
+
+        if braced {
+            self.read_character();
+            while self.char.is_ascii_hexdigit() && !self.eof {
+                hex.push(self.char);
+                self.read_character();
+            }
+        }
+
+        let closed = braced && self.char == '}' && !self.eof;
+        if closed {
+            self.read_character();
+        }
+
+        u32::from_str_radix(&hex, 16)
+            .ok()
+            .filter(|_| closed && hex.len() <= 6)
+            .and_then(char::from_u32)
+            .ok_or_else(|| {
+                Diagnostic::error(
+                    diagnostic::INVALID_ESCAPE,
+                    format!(
+                        "invalid unicode escape {}",
+                        self.input[position..self.position].to_owned()
+                    ),
+                    token::Span {
+                        end: self.offset(),
+                        ..start
+                    },
+                )
+                .with_note("unicode escapes are written \\u{...} with 1 to 6 hex digits")
+            })
+    }
+
+    const fn symbol(&self) -> Option<TokenKind> {
+        let kind = match self.char {
+            '=' => TokenKind::Assign,
+            ';' => TokenKind::Semicolon,
+            ':' => TokenKind::Colon,
+            '(' => TokenKind::LParen,
+            ')' => TokenKind::RParen,
+            ',' => TokenKind::Comma,
+            '+' => TokenKind::Plus,
+            '{' => TokenKind::LBrace,
+            '}' => TokenKind::RBrace,
+            '[' => TokenKind::LBracket,
+            ']' => TokenKind::RBracket,
+            '!' => TokenKind::Bang,
+            '-' => TokenKind::Minus,
+            '/' => TokenKind::Slash,
+            '*' => TokenKind::Asterisk,
+            '<' => TokenKind::Lt,
+            '>' => TokenKind::Gt,
+            _ => return None,
+        };
+
+        Some(kind)
+    }
+}
+
+/// Identifiers start with a Unicode XID_Start character or an underscore, and
+/// carry on with XID_Continue characters, which include digits and underscores
+fn is_identifier_start(char: char) -> bool {
+    char == '_' || unicode_ident::is_xid_start(char)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    const fn span_parts(span: token::Span) -> (usize, usize, usize, usize) {
+        (span.start, span.end, span.line, span.column)
+    }
+
     #[test]
     fn test_next_token() {
         let input = "=+(){},;";
         let mut lexer = Lexer::new(input);
 
         assert_eq!(
-            token::Token::new(token::ASSIGN, "="),
+            token::Token::new(TokenKind::Assign, "="),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::PLUS, "+"),
+            token::Token::new(TokenKind::Plus, "+"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::LPAREN, "("),
+            token::Token::new(TokenKind::LParen, "("),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::RPAREN, ")"),
+            token::Token::new(TokenKind::RParen, ")"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::LBRACE, "{"),
+            token::Token::new(TokenKind::LBrace, "{"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::RBRACE, "}"),
+            token::Token::new(TokenKind::RBrace, "}"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::COMMA, ","),
+            token::Token::new(TokenKind::Comma, ","),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::SEMICOLON, ";"),
+            token::Token::new(TokenKind::Semicolon, ";"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::EOF, ""),
+            token::Token::new(TokenKind::Eof, ""),
+            lexer.next_token().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_next_token_at_end_of_input() {
+        let mut lexer = Lexer::new("x + 10");
+
+        assert_eq!(
+            token::Token::new(TokenKind::Ident, "x"),
+            lexer.next_token().unwrap()
+        );
+        assert_eq!(
+            token::Token::new(TokenKind::Plus, "+"),
+            lexer.next_token().unwrap()
+        );
+        assert_eq!(
+            token::Token::new(TokenKind::Int, "10"),
+            lexer.next_token().unwrap()
+        );
+        assert_eq!(
+            token::Token::new(TokenKind::Eof, ""),
+            lexer.next_token().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_next_token_large_integers() {
+        let mut lexer = Lexer::new("3000000000 9223372036854775807 9223372036854775808");
+
+        assert_eq!(
+            token::Token::new(TokenKind::Int, "3000000000"),
+            lexer.next_token().unwrap()
+        );
+        assert_eq!(
+            token::Token::new(TokenKind::Int, "9223372036854775807"),
+            lexer.next_token().unwrap()
+        );
+
+        let err = lexer.next_token().unwrap_err();
+        assert_eq!(diagnostic::INVALID_NUMBER, err.code);
+        assert_eq!((31, 50), (err.span.start, err.span.end));
+        assert!(
+            err.notes[0].ends_with("-9223372036854775807 - 1"),
+            "{:?}",
+            err
+        );
+
+        let mut lexer = Lexer::new("9223372036854775808 0x1_0000_0000_0000_0000 1")
+            .with_integer_mode(IntegerMode::BigInt);
+        for literal in ["9223372036854775808", "18446744073709551616", "1"] {
+            assert_eq!(
+                token::Token::new(TokenKind::Int, literal),
+                lexer.next_token().unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_next_token_number_syntax() {
+        let mut lexer =
+            Lexer::new("0xff 0o17 0b1010 1_000_000 3.14 1e-9 2.5E+3 1_0.0_1 0x7fff_ffff_ffff_ffff");
+
+        for (kind, literal) in [
+            (TokenKind::Int, "255"),
+            (TokenKind::Int, "15"),
+            (TokenKind::Int, "10"),
+            (TokenKind::Int, "1000000"),
+            (TokenKind::Float, "3.14"),
+            (TokenKind::Float, "1e-9"),
+            (TokenKind::Float, "2.5e+3"),
+            (TokenKind::Float, "10.01"),
+            (TokenKind::Int, "9223372036854775807"),
+        ] {
+            assert_eq!(
+                token::Token::new(kind, literal),
+                lexer.next_token().unwrap()
+            );
+        }
+        assert_eq!(TokenKind::Eof, lexer.next_token().unwrap().kind);
+    }
+
+    #[test]
+    fn test_next_token_dot_after_integer() {
+        let mut lexer = Lexer::new("1.x");
+
+        assert_eq!(
+            token::Token::new(TokenKind::Int, "1"),
+            lexer.next_token().unwrap()
+        );
+        assert_eq!(
+            token::Token::new(TokenKind::Illegal, "."),
+            lexer.next_token().unwrap()
+        );
+        assert_eq!(
+            token::Token::new(TokenKind::Ident, "x"),
+            lexer.next_token().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_next_token_invalid_numbers() {
+        let tests = [
+            ("0x", "0x has no digits"),
+            ("0xfg", "invalid digit 'g' in 0xfg"),
+            ("0b102", "invalid digit '2' in 0b102"),
+            ("12abc", "invalid digit 'a' in 12abc"),
+            ("1__000", "misplaced underscore in 1__000"),
+            ("1_", "misplaced underscore in 1_"),
+            ("0x_1", "misplaced underscore in 0x_1"),
+            ("1e", "1e has no digits in its exponent"),
+            ("1e+x", "1e+ has no digits in its exponent"),
+            ("1e999", "could not parse 1e999 as a float: number too large"),
+            (
+                "0x1_0000_0000_0000_0000",
+                "could not parse 0x1_0000_0000_0000_0000 as an integer: number too large to fit in target type",
+            ),
+        ];
+
+        for (input, expected) in tests {
+            let err = Lexer::new(input).next_token().unwrap_err();
+            assert_eq!(diagnostic::INVALID_NUMBER, err.code, "{}", input);
+            assert_eq!(expected, err.message, "{}", input);
+            assert_eq!(0, err.span.start, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_next_token_strings() {
+        let mut lexer = Lexer::new(r#""foobar" "foo bar" "a\n\t\"b\"\\" "\u{1F600}\u{e9}" """#);
+
+        for expected in ["foobar", "foo bar", "a\n\t\"b\"\\", "\u{1F600}\u{e9}", ""] {
+            assert_eq!(
+                token::Token::new(TokenKind::String, expected),
+                lexer.next_token().unwrap()
+            );
+        }
+        assert_eq!(TokenKind::Eof, lexer.next_token().unwrap().kind);
+    }
+
+    #[test]
+    fn test_next_token_string_errors() {
+        let cases = [
+            (r#"x = "abc"#, diagnostic::UNTERMINATED_STRING, 4, 5),
+            (r#""a\qb""#, diagnostic::INVALID_ESCAPE, 2, 4),
+            (r#""\u{110000}""#, diagnostic::INVALID_ESCAPE, 1, 11),
+            (r#""\u{41""#, diagnostic::INVALID_ESCAPE, 1, 6),
+            (r#""\u41""#, diagnostic::INVALID_ESCAPE, 1, 3),
+        ];
+
+        for (input, code, start, end) in cases {
+            let mut lexer = Lexer::new(input);
+            let err = loop {
+                match lexer.next_token() {
+                    Ok(token) if token.kind == TokenKind::Eof => panic!("no error in {}", input),
+                    Ok(_) => {}
+                    Err(err) => break err,
+                }
+            };
+
+            assert_eq!(code, err.code, "input: {}", input);
+            assert_eq!(
+                (start, end),
+                (err.span.start, err.span.end),
+                "input: {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_next_token_after_bad_escape() {
+        let mut lexer = Lexer::new(r#""\q" + 1"#);
+
+        assert!(lexer.next_token().is_err());
+        assert_eq!(
+            token::Token::new(TokenKind::Plus, "+"),
+            lexer.next_token().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_new_at() {
+        let mut lexer = Lexer::new_at("let\n x", 100);
+
+        assert_eq!(
+            (100, 103, 1, 1),
+            span_parts(lexer.next_token().unwrap().span)
+        );
+        assert_eq!(
+            (105, 106, 2, 2),
+            span_parts(lexer.next_token().unwrap().span)
+        );
+        assert_eq!(
+            (106, 106, 2, 3),
+            span_parts(lexer.next_token().unwrap().span)
+        );
+
+        let err = Lexer::new_at("1e", 100).next_token().unwrap_err();
+        assert_eq!((100, 102), (err.span.start, err.span.end));
+    }
+
+    #[test]
+    fn test_next_token_spans() {
+        let input = "let x = 10;\n  x != 1";
+        let mut lexer = Lexer::new(input);
+
+        let expected = [
+            (TokenKind::Let, 0, 3, 1, 1),
+            (TokenKind::Ident, 4, 5, 1, 5),
+            (TokenKind::Assign, 6, 7, 1, 7),
+            (TokenKind::Int, 8, 10, 1, 9),
+            (TokenKind::Semicolon, 10, 11, 1, 11),
+            (TokenKind::Ident, 14, 15, 2, 3),
+            (TokenKind::NotEq, 16, 18, 2, 5),
+            (TokenKind::Int, 19, 20, 2, 8),
+            (TokenKind::Eof, 20, 20, 2, 9),
+        ];
+
+        for (token_type, start, end, line, column) in expected {
+            let token = lexer.next_token().unwrap();
+            assert_eq!(token_type, token.kind);
+            assert_eq!(
+                token::Span {
+                    start,
+                    end,
+                    line,
+                    column
+                },
+                token.span,
+                "token: {:?}",
+                token
+            );
+            assert_eq!(token.literal(), &input[start..end]);
+        }
+    }
+
+    #[test]
+    fn test_next_token_spans_count_bytes() {
+        let mut lexer = Lexer::new("é ü");
+
+        let first = lexer.next_token().unwrap();
+        let second = lexer.next_token().unwrap();
+
+        assert_eq!(
+            (0, 2, 1),
+            (first.span.start, first.span.end, first.span.column)
+        );
+        assert_eq!(
+            (3, 5, 3),
+            (second.span.start, second.span.end, second.span.column)
+        );
+    }
+
+    #[test]
+    fn test_next_token_identifiers() {
+        let input = "my_var x1 _private __init café 变量 Δx letter";
+        let mut lexer = Lexer::new(input);
+
+        for expected in [
+            "my_var", "x1", "_private", "__init", "café", "变量", "Δx", "letter",
+        ] {
+            let token = lexer.next_token().unwrap();
+            assert_eq!(token::Token::new(TokenKind::Ident, expected), token);
+            assert_eq!(expected, &input[token.span.start..token.span.end]);
+        }
+        assert_eq!(TokenKind::Eof, lexer.next_token().unwrap().kind);
+    }
+
+    #[test]
+    fn test_next_token_non_identifier_characters() {
+        // Emoji and combining marks cannot start an identifier
+        let mut lexer = Lexer::new("😀 \u{301}a");
+
+        assert_eq!(
+            token::Token::new(TokenKind::Illegal, "😀"),
+            lexer.next_token().unwrap()
+        );
+        assert_eq!(
+            token::Token::new(TokenKind::Illegal, "\u{301}"),
+            lexer.next_token().unwrap()
+        );
+        assert_eq!(
+            token::Token::new(TokenKind::Ident, "a"),
             lexer.next_token().unwrap()
         );
     }
@@ -196,27 +869,27 @@ mod tests {
         let mut lexer = Lexer::new(input);
 
         assert_eq!(
-            token::Token::new(token::LET, "let"),
+            token::Token::new(TokenKind::Let, "let"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::IDENT, "five"),
+            token::Token::new(TokenKind::Ident, "five"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::ASSIGN, "="),
+            token::Token::new(TokenKind::Assign, "="),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::INT, "5"),
+            token::Token::new(TokenKind::Int, "5"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::SEMICOLON, ";"),
+            token::Token::new(TokenKind::Semicolon, ";"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::EOF, ""),
+            token::Token::new(TokenKind::Eof, ""),
             lexer.next_token().unwrap()
         );
     }
@@ -243,304 +916,324 @@ mod tests {
 
             10 == 10;
             10 != 9;
+            [1, 2];
+            {"foo": "bar"}
         "#;
 
         let mut lexer = Lexer::new(input);
 
         assert_eq!(
-            token::Token::new(token::LET, "let"),
+            token::Token::new(TokenKind::Let, "let"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::IDENT, "five"),
+            token::Token::new(TokenKind::Ident, "five"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::ASSIGN, "="),
+            token::Token::new(TokenKind::Assign, "="),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::INT, "5"),
+            token::Token::new(TokenKind::Int, "5"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::SEMICOLON, ";"),
+            token::Token::new(TokenKind::Semicolon, ";"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::LET, "let"),
+            token::Token::new(TokenKind::Let, "let"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::IDENT, "ten"),
+            token::Token::new(TokenKind::Ident, "ten"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::ASSIGN, "="),
+            token::Token::new(TokenKind::Assign, "="),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::INT, "10"),
+            token::Token::new(TokenKind::Int, "10"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::SEMICOLON, ";"),
+            token::Token::new(TokenKind::Semicolon, ";"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::LET, "let"),
+            token::Token::new(TokenKind::Let, "let"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::IDENT, "add"),
+            token::Token::new(TokenKind::Ident, "add"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::ASSIGN, "="),
+            token::Token::new(TokenKind::Assign, "="),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::FUNCTION, "fn"),
+            token::Token::new(TokenKind::Function, "fn"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::LPAREN, "("),
+            token::Token::new(TokenKind::LParen, "("),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::IDENT, "x"),
+            token::Token::new(TokenKind::Ident, "x"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::COMMA, ","),
+            token::Token::new(TokenKind::Comma, ","),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::IDENT, "y"),
+            token::Token::new(TokenKind::Ident, "y"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::RPAREN, ")"),
+            token::Token::new(TokenKind::RParen, ")"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::LBRACE, "{"),
+            token::Token::new(TokenKind::LBrace, "{"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::IDENT, "x"),
+            token::Token::new(TokenKind::Ident, "x"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::PLUS, "+"),
+            token::Token::new(TokenKind::Plus, "+"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::IDENT, "y"),
+            token::Token::new(TokenKind::Ident, "y"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::SEMICOLON, ";"),
+            token::Token::new(TokenKind::Semicolon, ";"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::RBRACE, "}"),
+            token::Token::new(TokenKind::RBrace, "}"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::SEMICOLON, ";"),
+            token::Token::new(TokenKind::Semicolon, ";"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::LET, "let"),
+            token::Token::new(TokenKind::Let, "let"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::IDENT, "result"),
+            token::Token::new(TokenKind::Ident, "result"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::ASSIGN, "="),
+            token::Token::new(TokenKind::Assign, "="),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::IDENT, "add"),
+            token::Token::new(TokenKind::Ident, "add"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::LPAREN, "("),
+            token::Token::new(TokenKind::LParen, "("),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::IDENT, "five"),
+            token::Token::new(TokenKind::Ident, "five"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::COMMA, ","),
+            token::Token::new(TokenKind::Comma, ","),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::IDENT, "ten"),
+            token::Token::new(TokenKind::Ident, "ten"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::RPAREN, ")"),
+            token::Token::new(TokenKind::RParen, ")"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::SEMICOLON, ";"),
+            token::Token::new(TokenKind::Semicolon, ";"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::BANG, "!"),
+            token::Token::new(TokenKind::Bang, "!"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::MINUS, "-"),
+            token::Token::new(TokenKind::Minus, "-"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::SLASH, "/"),
+            token::Token::new(TokenKind::Slash, "/"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::ASTERISK, "*"),
+            token::Token::new(TokenKind::Asterisk, "*"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::INT, "5"),
+            token::Token::new(TokenKind::Int, "5"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::SEMICOLON, ";"),
+            token::Token::new(TokenKind::Semicolon, ";"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::INT, "5"),
+            token::Token::new(TokenKind::Int, "5"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::LT, "<"),
+            token::Token::new(TokenKind::Lt, "<"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::INT, "10"),
+            token::Token::new(TokenKind::Int, "10"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::GT, ">"),
+            token::Token::new(TokenKind::Gt, ">"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::INT, "5"),
+            token::Token::new(TokenKind::Int, "5"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::SEMICOLON, ";"),
+            token::Token::new(TokenKind::Semicolon, ";"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::IF, "if"),
+            token::Token::new(TokenKind::If, "if"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::LPAREN, "("),
+            token::Token::new(TokenKind::LParen, "("),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::INT, "5"),
+            token::Token::new(TokenKind::Int, "5"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::LT, "<"),
+            token::Token::new(TokenKind::Lt, "<"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::INT, "10"),
+            token::Token::new(TokenKind::Int, "10"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::RPAREN, ")"),
+            token::Token::new(TokenKind::RParen, ")"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::LBRACE, "{"),
+            token::Token::new(TokenKind::LBrace, "{"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::RETURN, "return"),
+            token::Token::new(TokenKind::Return, "return"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::TRUE, "true"),
+            token::Token::new(TokenKind::True, "true"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::SEMICOLON, ";"),
+            token::Token::new(TokenKind::Semicolon, ";"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::RBRACE, "}"),
+            token::Token::new(TokenKind::RBrace, "}"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::ELSE, "else"),
+            token::Token::new(TokenKind::Else, "else"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::LBRACE, "{"),
+            token::Token::new(TokenKind::LBrace, "{"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::RETURN, "return"),
+            token::Token::new(TokenKind::Return, "return"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::FALSE, "false"),
+            token::Token::new(TokenKind::False, "false"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::SEMICOLON, ";"),
+            token::Token::new(TokenKind::Semicolon, ";"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::RBRACE, "}"),
+            token::Token::new(TokenKind::RBrace, "}"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::INT, "10"),
+            token::Token::new(TokenKind::Int, "10"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::EQ, "=="),
+            token::Token::new(TokenKind::Eq, "=="),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::INT, "10"),
+            token::Token::new(TokenKind::Int, "10"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::SEMICOLON, ";"),
+            token::Token::new(TokenKind::Semicolon, ";"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::INT, "10"),
+            token::Token::new(TokenKind::Int, "10"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::NOT_EQ, "!="),
+            token::Token::new(TokenKind::NotEq, "!="),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::INT, "9"),
+            token::Token::new(TokenKind::Int, "9"),
             lexer.next_token().unwrap()
         );
         assert_eq!(
-            token::Token::new(token::SEMICOLON, ";"),
+            token::Token::new(TokenKind::Semicolon, ";"),
             lexer.next_token().unwrap()
         );
+        for (kind, literal) in [
+            (TokenKind::LBracket, "["),
+            (TokenKind::Int, "1"),
+            (TokenKind::Comma, ","),
+            (TokenKind::Int, "2"),
+            (TokenKind::RBracket, "]"),
+            (TokenKind::Semicolon, ";"),
+            (TokenKind::LBrace, "{"),
+            (TokenKind::String, "foo"),
+            (TokenKind::Colon, ":"),
+            (TokenKind::String, "bar"),
+            (TokenKind::RBrace, "}"),
+        ] {
+            assert_eq!(
+                token::Token::new(kind, literal),
+                lexer.next_token().unwrap()
+            );
+        }
         assert_eq!(
-            token::Token::new(token::EOF, ""),
+            token::Token::new(TokenKind::Eof, ""),
             lexer.next_token().unwrap()
         );
     }
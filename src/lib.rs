@@ -0,0 +1,39 @@
+//! A Interpreter for the Monkey language
+//!
+//! [`Interpreter`] runs Monkey code from a host program, which can share values with
+//! the script and give it functions written in Rust:
+//!
+//! ```
+//! use monkey::{Interpreter, Object};
+//!
+//! let mut interpreter = Interpreter::new();
+//! interpreter.set_global("base", 40);
+//! interpreter.register_native_fn("double", |args: &[Object]| {
+//!     let n = args.first().cloned().unwrap_or(Object::Null);
+//!     i64::try_from(n).map(|n| n * 2)
+//! });
+//!
+//! let port = interpreter.eval_str("let port = base + double(1); port").unwrap();
+//! assert_eq!(Object::Integer(42), port);
+//! assert_eq!(Some(Object::Integer(42)), interpreter.get_global("port"));
+//! ```
+pub mod ast;
+pub mod builtins;
+pub mod code;
+pub mod compiler;
+pub mod diagnostic;
+pub mod environment;
+pub mod evaluator;
+pub mod integer_mode;
+pub mod interpreter;
+pub mod interrupt;
+pub mod lexer;
+pub mod mkc;
+pub mod object;
+pub mod parser;
+pub mod symbol_table;
+pub mod token;
+pub mod vm;
+
+pub use interpreter::Interpreter;
+pub use object::Object;
@@ -1,11 +1,301 @@
-//! A Interpreter for the Monkey language
-pub mod ast;
-pub mod lexer;
-pub mod parser;
-pub mod repl;
-pub mod token;
-
-fn main() -> Result<(), anyhow::Error> {
-    repl::start()?;
-    Ok(())
+//! Command line front end for the Monkey interpreter
+mod repl;
+
+use monkey::compiler::{self, Bytecode};
+use monkey::evaluator::IntegerMode;
+use monkey::interpreter::{Error, Interpreter};
+use monkey::lexer::Lexer;
+use monkey::mkc;
+use monkey::parser::Parser;
+use monkey::vm::Vm;
+use monkey::Object;
+use std::io::{IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+const USAGE: &str = "\
+Usage: monkey [OPTIONS] [FILE]
+       monkey compile [--strip] FILE [-o OUT]
+       monkey disasm FILE
+
+Runs the Monkey program in FILE, which is either source or bytecode made by
+`monkey compile`.  With no FILE, starts the REPL, or runs the program piped in on
+stdin.  A FILE of - always reads the program from stdin.
+
+Commands:
+  compile  Compile FILE to bytecode, written to OUT or to FILE with a .mkc
+           extension
+  disasm   Print the bytecode compiled from FILE, annotated with its source
+
+Options:
+  -e, --eval <CODE>    Evaluate CODE and print the result
+  -o, --output <OUT>   Where compile writes the bytecode
+      --strip          Leave out the debug table, so runtime errors in the
+                       bytecode cannot point at the source
+      --bigint         Give integer literals and arithmetic too large for 64
+                       bits an arbitrary precision value rather than raising
+                       an error.  Not accepted by compile or disasm
+  -h, --help           Print this help
+
+Exit status:
+  0   the program ran to completion
+  64  the command line was invalid
+  65  the program failed to parse or compile, or the bytecode is invalid
+  66  the program could not be read
+  70  the program raised a runtime error
+  73  the bytecode could not be written";
+
+// Exit codes, following the BSD sysexits convention
+const EXIT_USAGE: u8 = 64;
+const EXIT_DATA_ERROR: u8 = 65;
+const EXIT_NO_INPUT: u8 = 66;
+const EXIT_RUNTIME_ERROR: u8 = 70;
+const EXIT_CANT_CREATE: u8 = 73;
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+
+    let (integer_mode, args) = take_bigint_flag(&args);
+
+    match args.as_slice() {
+        [] if std::io::stdin().is_terminal() => match repl::start(integer_mode) {
+            Ok(()) => ExitCode::SUCCESS,
+            Err(err) => {
+                eprintln!("error: {}", err);
+                ExitCode::FAILURE
+            }
+        },
+        [] | ["-"] => run_stdin(integer_mode),
+        ["-h" | "--help"] => {
+            println!("{}", USAGE);
+            ExitCode::SUCCESS
+        }
+        ["-e" | "--eval", code] => run("<eval>", code, true, integer_mode),
+        ["compile" | "disasm", ..] if integer_mode == IntegerMode::BigInt => {
+            eprintln!("error: --bigint only applies when running a program\n");
+            usage_error()
+        }
+        ["compile", args @ ..] => compile_command(args),
+        ["disasm", path] if !path.starts_with('-') => disasm_command(path),
+        [path] if !path.starts_with('-') => run_file(path, integer_mode),
+        _ => usage_error(),
+    }
+}
+
+/// Removes `--bigint` from wherever it is in `args`, but not when it is the value
+/// of an option such as `-e`
+fn take_bigint_flag<'a>(args: &[&'a str]) -> (IntegerMode, Vec<&'a str>) {
+    let mut integer_mode = IntegerMode::Checked;
+    let mut rest = Vec::with_capacity(args.len());
+
+    let mut args = args.iter();
+    while let Some(&arg) = args.next() {
+        match arg {
+            "--bigint" => integer_mode = IntegerMode::BigInt,
+            "-e" | "--eval" | "-o" | "--output" => {
+                rest.push(arg);
+                rest.extend(args.next());
+            }
+            _ => rest.push(arg),
+        }
+    }
+
+    (integer_mode, rest)
+}
+
+fn usage_error() -> ExitCode {
+    eprintln!("{}", USAGE);
+    ExitCode::from(EXIT_USAGE)
+}
+
+fn run_stdin(integer_mode: IntegerMode) -> ExitCode {
+    let mut source = String::new();
+
+    if let Err(err) = std::io::stdin().read_to_string(&mut source) {
+        eprintln!("error: could not read stdin: {}", err);
+        return ExitCode::from(EXIT_NO_INPUT);
+    }
+
+    run("<stdin>", &source, false, integer_mode)
+}
+
+fn run_file(path: &str, integer_mode: IntegerMode) -> ExitCode {
+    let bytes = match std::fs::read(path) {
+        Ok(bytes) => bytes,
+        Err(err) => {
+            eprintln!("error: could not read {}: {}", path, err);
+            return ExitCode::from(EXIT_NO_INPUT);
+        }
+    };
+
+    if path.ends_with(".mkc") || mkc::is_bytecode(&bytes) {
+        return run_bytecode(path, &bytes, integer_mode);
+    }
+
+    match String::from_utf8(bytes) {
+        Ok(source) => run(path, &source, false, integer_mode),
+        Err(err) => {
+            eprintln!("error: could not read {}: {}", path, err.utf8_error());
+            ExitCode::from(EXIT_NO_INPUT)
+        }
+    }
+}
+
+/// Runs bytecode made by `monkey compile`.  A runtime error is shown against the
+/// source the bytecode was compiled from, if it has a debug table and that source
+/// can still be read and has not changed since
+fn run_bytecode(path: &str, bytes: &[u8], integer_mode: IntegerMode) -> ExitCode {
+    let file = match mkc::decode(bytes) {
+        Ok(file) => file,
+        Err(err) => {
+            eprintln!("error: could not load {}: {}", path, err);
+            return ExitCode::from(EXIT_DATA_ERROR);
+        }
+    };
+
+    let mut vm = Vm::new(file.bytecode).with_integer_mode(integer_mode);
+    let Some(err) = vm.run().to_diagnostic() else {
+        return ExitCode::SUCCESS;
+    };
+
+    match file.source {
+        Some(source) => match std::fs::read_to_string(&source.name) {
+            Ok(text) if source.matches(&text) => eprint!("{}", err.render(&source.name, &text)),
+            _ => eprintln!("{}: {}", source.name, err),
+        },
+        None => eprintln!("error: {}", err.message),
+    }
+    ExitCode::from(EXIT_RUNTIME_ERROR)
+}
+
+/// `monkey compile`, which writes the bytecode for a source file to a `.mkc` file
+fn compile_command(args: &[&str]) -> ExitCode {
+    let mut input = None;
+    let mut output = None;
+    let mut strip = false;
+
+    let mut args = args.iter();
+    while let Some(&arg) = args.next() {
+        match arg {
+            "-o" | "--output" => match args.next() {
+                Some(&path) if output.is_none() => output = Some(PathBuf::from(path)),
+                _ => return usage_error(),
+            },
+            "--strip" => strip = true,
+            _ if input.is_none() && !arg.starts_with('-') => input = Some(arg),
+            _ => return usage_error(),
+        }
+    }
+
+    let Some(input) = input else {
+        return usage_error();
+    };
+    let output = output.unwrap_or_else(|| Path::new(input).with_extension("mkc"));
+
+    let source = match std::fs::read_to_string(input) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("error: could not read {}: {}", input, err);
+            return ExitCode::from(EXIT_NO_INPUT);
+        }
+    };
+    let bytecode = match compile(input, &source) {
+        Ok(bytecode) => bytecode,
+        Err(code) => return code,
+    };
+
+    let file = mkc::File {
+        bytecode,
+        source: (!strip).then(|| mkc::Source::new(input, &source)),
+    };
+    if let Err(err) = std::fs::write(&output, mkc::encode(&file)) {
+        eprintln!("error: could not write {}: {}", output.display(), err);
+        return ExitCode::from(EXIT_CANT_CREATE);
+    }
+
+    ExitCode::SUCCESS
+}
+
+/// `monkey disasm`, which prints the bytecode compiled from a source file
+fn disasm_command(path: &str) -> ExitCode {
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(err) => {
+            eprintln!("error: could not read {}: {}", path, err);
+            return ExitCode::from(EXIT_NO_INPUT);
+        }
+    };
+
+    match compile(path, &source) {
+        Ok(bytecode) => {
+            print!("{}", bytecode.disassemble(&source));
+            ExitCode::SUCCESS
+        }
+        Err(code) => code,
+    }
+}
+
+/// Parses and compiles `source`, rendering any diagnostics against it under `name`
+fn compile(name: &str, source: &str) -> Result<Bytecode, ExitCode> {
+    let mut parser = Parser::new(Lexer::new(source));
+    let program = parser.parse_program();
+
+    if !parser.errors.is_empty() {
+        for err in &parser.errors {
+            eprint!("{}", err.render(name, source));
+        }
+        return Err(ExitCode::from(EXIT_DATA_ERROR));
+    }
+
+    compiler::compile(&program).map_err(|err| {
+        eprint!("{}", err.render(name, source));
+        ExitCode::from(EXIT_DATA_ERROR)
+    })
+}
+
+/// Parses and evaluates `source`, rendering any diagnostics against it under `name`.
+/// The final value is printed only when `print_result` is set, e.g. for `-e`
+fn run(name: &str, source: &str, print_result: bool, integer_mode: IntegerMode) -> ExitCode {
+    match Interpreter::with_integer_mode(integer_mode).eval_str(source) {
+        Ok(result) => {
+            if print_result && result != Object::Null {
+                println!("{}", result);
+            }
+            ExitCode::SUCCESS
+        }
+        Err(Error::Parse(errors)) => {
+            for err in &errors {
+                eprint!("{}", err.render(name, source));
+            }
+            ExitCode::from(EXIT_DATA_ERROR)
+        }
+        Err(Error::Runtime(err)) => {
+            eprint!("{}", err.render(name, source));
+            ExitCode::from(EXIT_RUNTIME_ERROR)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take_bigint_flag() {
+        let cases: [(&[&str], IntegerMode, &[&str]); 4] = [
+            (&["prog.mk"], IntegerMode::Checked, &["prog.mk"]),
+            (&["--bigint", "prog.mk"], IntegerMode::BigInt, &["prog.mk"]),
+            (&["-e", "1", "--bigint"], IntegerMode::BigInt, &["-e", "1"]),
+            (
+                &["-e", "--bigint"],
+                IntegerMode::Checked,
+                &["-e", "--bigint"],
+            ),
+        ];
+
+        for (args, mode, rest) in cases {
+            assert_eq!((mode, rest.to_vec()), take_bigint_flag(args), "{:?}", args);
+        }
+    }
 }
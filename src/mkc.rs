@@ -0,0 +1,623 @@
+//! The `.mkc` file format, which holds a compiled program so it can be run without
+//! its source.  Every number is big-endian, like instruction operands:
+//!
+//! ```text
+//! file      = magic:"MKC\0" version:u16 flags:u8 [source:string hash:u64]
+//!             globals:u32 string* constants:u32 constant* main:function
+//! constant  = 0:u8 i64 | 1:u8 string | 2:u8 function | 3:u8 f64
+//! function  = parameters:u32 locals:u32 string* cells:u32 u32* free:u32 free*
+//!             instructions:u32 u8*
+//!             [positions:u32 (offset:u32 start:u32 end:u32 line:u32 column:u32)*]
+//! free      = name:string (0:u8 slot:u32 | 1:u8 index:u32)
+//! string    = length:u32 utf-8 bytes
+//! ```
+//!
+//! A function's parameters are the first of its locals, and its cells are the slots
+//! of the locals kept in cells.  A free variable is captured from the cell of a
+//! local or from a free variable of the closure that makes it.  The bracketed parts make
+//! up the debug table, which maps instructions back to the
+//! source so runtime errors can point at it.  They are present when bit 0 of the
+//! flags is set.  The hash is `source_hash` of the source's text
+use core::fmt;
+use std::rc::Rc;
+
+use crate::builtins;
+use crate::code::{self, Opcode};
+use crate::compiler::Bytecode;
+use crate::object::{Capture, CompiledFunction, FreeVariable, Object};
+use crate::token::Span;
+
+pub const MAGIC: [u8; 4] = *b"MKC\0";
+/// Bumped whenever the layout or the meaning of an opcode changes, as older
+/// files cannot be run correctly after that
+pub const VERSION: u16 = 2;
+
+const FLAG_DEBUG: u8 = 1;
+
+const TAG_INTEGER: u8 = 0;
+const TAG_STRING: u8 = 1;
+const TAG_FUNCTION: u8 = 2;
+const TAG_FLOAT: u8 = 3;
+
+const CAPTURE_LOCAL: u8 = 0;
+const CAPTURE_FREE: u8 = 1;
+
+/// A program as stored in a `.mkc` file
+#[derive(Debug, Clone, PartialEq)]
+pub struct File {
+    pub bytecode: Bytecode,
+    /// The source the program was compiled from, if the file has a debug table
+    pub source: Option<Source>,
+}
+
+/// The source file a program was compiled from.  Runtime errors can be shown
+/// against it while its text still has the same hash
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Source {
+    pub name: String,
+    pub hash: u64,
+}
+
+impl Source {
+    #[must_use]
+    pub fn new(name: &str, text: &str) -> Self {
+        Self {
+            name: name.to_owned(),
+            hash: source_hash(text),
+        }
+    }
+
+    /// Whether `text` is the source the program was compiled from
+    #[must_use]
+    pub fn matches(&self, text: &str) -> bool {
+        source_hash(text) == self.hash
+    }
+}
+
+/// The 64-bit FNV-1a hash of `text`, which unlike `std`'s hashers is the same
+/// across releases
+#[must_use]
+pub fn source_hash(text: &str) -> u64 {
+    text.bytes().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Why a `.mkc` file could not be loaded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// The file does not start with `MAGIC`
+    NotBytecode,
+    UnsupportedVersion(u16),
+    /// The file ends in the middle of a section
+    Truncated,
+    /// The file is complete but holds something a compiler would never write
+    Corrupt(String),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotBytecode => write!(f, "not a Monkey bytecode file"),
+            Self::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported bytecode version {} (expected {})",
+                version, VERSION
+            ),
+            Self::Truncated => write!(f, "file is truncated"),
+            Self::Corrupt(reason) => write!(f, "file is corrupt: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+/// Whether `bytes` start like a `.mkc` file
+#[must_use]
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.starts_with(&MAGIC)
+}
+
+/// Writes `file` out in the `.mkc` format.  Without a `source` the debug table
+/// is left out
+#[must_use]
+pub fn encode(file: &File) -> Vec<u8> {
+    let mut encoder = Encoder {
+        out: MAGIC.to_vec(),
+        debug: file.source.is_some(),
+    };
+    encoder.out.extend_from_slice(&VERSION.to_be_bytes());
+
+    match &file.source {
+        Some(source) => {
+            encoder.out.push(FLAG_DEBUG);
+            encoder.string(&source.name);
+            encoder.out.extend_from_slice(&source.hash.to_be_bytes());
+        }
+        None => encoder.out.push(0),
+    }
+
+    let bytecode = &file.bytecode;
+    encoder.u32(bytecode.globals.len());
+    for name in &bytecode.globals {
+        encoder.string(name);
+    }
+
+    encoder.u32(bytecode.constants.len());
+    for constant in &bytecode.constants {
+        encoder.constant(constant);
+    }
+
+    encoder.function(&bytecode.main);
+    encoder.out
+}
+
+/// Reads a `.mkc` file, checking that every instruction is well formed and refers
+/// to a constant, global or local that exists
+///
+/// # Errors
+/// Returns an error if `bytes` are not a `.mkc` file of this version, or are
+/// truncated or corrupt
+pub fn decode(bytes: &[u8]) -> Result<File, DecodeError> {
+    if !is_bytecode(bytes) {
+        return Err(DecodeError::NotBytecode);
+    }
+
+    let mut decoder = Decoder {
+        bytes,
+        offset: MAGIC.len(),
+        debug: false,
+    };
+
+    let version = decoder.u16()?;
+    if version != VERSION {
+        return Err(DecodeError::UnsupportedVersion(version));
+    }
+
+    let flags = decoder.u8()?;
+    if flags & !FLAG_DEBUG != 0 {
+        return Err(corrupt(format!("unknown flags {:#04x}", flags)));
+    }
+    decoder.debug = flags & FLAG_DEBUG != 0;
+    let source = if decoder.debug {
+        Some(Source {
+            name: decoder.string()?,
+            hash: u64::from_be_bytes(decoder.array()?),
+        })
+    } else {
+        None
+    };
+
+    let globals = (0..decoder.u32()?)
+        .map(|_| decoder.string())
+        .collect::<Result<Vec<_>, _>>()?;
+    let constants = (0..decoder.u32()?)
+        .map(|_| decoder.constant())
+        .collect::<Result<Vec<_>, _>>()?;
+    let main = decoder.function()?;
+
+    if decoder.offset != bytes.len() {
+        return Err(corrupt(format!(
+            "{} unexpected bytes after the program",
+            bytes.len() - decoder.offset
+        )));
+    }
+
+    let bytecode = Bytecode {
+        main,
+        constants,
+        globals,
+    };
+    validate(&bytecode)?;
+
+    Ok(File { bytecode, source })
+}
+
+struct Encoder {
+    out: Vec<u8>,
+    debug: bool,
+}
+
+impl Encoder {
+    fn u32(&mut self, value: usize) {
+        let value = u32::try_from(value).expect("bytecode sections are smaller than 4GiB");
+        self.out.extend_from_slice(&value.to_be_bytes());
+    }
+
+    fn string(&mut self, value: &str) {
+        self.u32(value.len());
+        self.out.extend_from_slice(value.as_bytes());
+    }
+
+    fn constant(&mut self, constant: &Object) {
+        match constant {
+            Object::Integer(value) => {
+                self.out.push(TAG_INTEGER);
+                self.out.extend_from_slice(&value.to_be_bytes());
+            }
+            Object::String(value) => {
+                self.out.push(TAG_STRING);
+                self.string(value);
+            }
+            Object::CompiledFunction(function) => {
+                self.out.push(TAG_FUNCTION);
+                self.function(function);
+            }
+            Object::Float(value) => {
+                self.out.push(TAG_FLOAT);
+                self.out.extend_from_slice(&value.to_be_bytes());
+            }
+            other => unreachable!("the compiler does not make {} constants", other.type_name()),
+        }
+    }
+
+    fn function(&mut self, function: &CompiledFunction) {
+        self.u32(function.parameters.len());
+        self.u32(function.locals.len());
+        for local in &function.locals {
+            self.string(local);
+        }
+        self.u32(function.cells.len());
+        for cell in &function.cells {
+            self.u32(*cell);
+        }
+        self.u32(function.free.len());
+        for free in &function.free {
+            self.string(&free.name);
+            let (tag, index) = match free.capture {
+                Capture::Local(slot) => (CAPTURE_LOCAL, slot),
+                Capture::Free(index) => (CAPTURE_FREE, index),
+            };
+            self.out.push(tag);
+            self.u32(index);
+        }
+        self.u32(function.instructions.len());
+        self.out.extend_from_slice(&function.instructions);
+
+        if self.debug {
+            self.u32(function.positions.len());
+            for (offset, span) in &function.positions {
+                for value in [*offset, span.start, span.end, span.line, span.column] {
+                    self.u32(value);
+                }
+            }
+        }
+    }
+}
+
+struct Decoder<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+    debug: bool,
+}
+
+impl Decoder<'_> {
+    fn take(&mut self, len: usize) -> Result<&[u8], DecodeError> {
+        let end = self
+            .offset
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(DecodeError::Truncated)?;
+        let bytes = &self.bytes[self.offset..end];
+        self.offset = end;
+        Ok(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], DecodeError> {
+        let mut array = [0; N];
+        array.copy_from_slice(self.take(N)?);
+        Ok(array)
+    }
+
+    fn u8(&mut self) -> Result<u8, DecodeError> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, DecodeError> {
+        Ok(u16::from_be_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<usize, DecodeError> {
+        let value = u32::from_be_bytes(self.array()?);
+        usize::try_from(value).map_err(|_| corrupt(format!("{} is too large", value)))
+    }
+
+    fn string(&mut self) -> Result<String, DecodeError> {
+        let len = self.u32()?;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| corrupt("a string is not valid UTF-8"))
+    }
+
+    fn constant(&mut self) -> Result<Object, DecodeError> {
+        match self.u8()? {
+            TAG_INTEGER => Ok(Object::Integer(i64::from_be_bytes(self.array()?))),
+            TAG_STRING => Ok(Object::String(self.string()?)),
+            TAG_FUNCTION => Ok(Object::CompiledFunction(Rc::new(self.function()?))),
+            TAG_FLOAT => Ok(Object::Float(f64::from_be_bytes(self.array()?))),
+            tag => Err(corrupt(format!("unknown constant tag {}", tag))),
+        }
+    }
+
+    fn function(&mut self) -> Result<CompiledFunction, DecodeError> {
+        let num_parameters = self.u32()?;
+        let locals = (0..self.u32()?)
+            .map(|_| self.string())
+            .collect::<Result<Vec<_>, _>>()?;
+        let parameters = locals
+            .get(..num_parameters)
+            .ok_or_else(|| corrupt("a function has more parameters than locals"))?
+            .to_vec();
+        let cells = (0..self.u32()?)
+            .map(|_| self.u32())
+            .collect::<Result<Vec<_>, _>>()?;
+        let free = (0..self.u32()?)
+            .map(|_| {
+                let name = self.string()?;
+                let capture = match self.u8()? {
+                    CAPTURE_LOCAL => Capture::Local(self.u32()?),
+                    CAPTURE_FREE => Capture::Free(self.u32()?),
+                    tag => return Err(corrupt(format!("unknown capture tag {}", tag))),
+                };
+                Ok(FreeVariable { name, capture })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        let len = self.u32()?;
+        let instructions = self.take(len)?.to_vec();
+
+        let mut positions = Vec::new();
+        if self.debug {
+            for _ in 0..self.u32()? {
+                let offset = self.u32()?;
+                let span = Span {
+                    start: self.u32()?,
+                    end: self.u32()?,
+                    line: self.u32()?,
+                    column: self.u32()?,
+                };
+                positions.push((offset, span));
+            }
+        }
+
+        Ok(CompiledFunction {
+            instructions,
+            parameters,
+            locals,
+            cells,
+            free,
+            positions,
+        })
+    }
+}
+
+fn corrupt(reason: impl Into<String>) -> DecodeError {
+    DecodeError::Corrupt(reason.into())
+}
+
+/// Checks the instructions of every function, so the virtual machine never meets
+/// an opcode it does not know or an operand that points nowhere
+fn validate(bytecode: &Bytecode) -> Result<(), DecodeError> {
+    let functions = bytecode
+        .constants
+        .iter()
+        .filter_map(|constant| match constant {
+            Object::CompiledFunction(function) => Some(&**function),
+            _ => None,
+        });
+
+    for function in std::iter::once(&bytecode.main).chain(functions) {
+        validate_function(bytecode, function)?;
+    }
+
+    Ok(())
+}
+
+fn validate_function(bytecode: &Bytecode, function: &CompiledFunction) -> Result<(), DecodeError> {
+    let instructions = &function.instructions;
+
+    // Decode everything first, as jumps may go forwards
+    let decoded = code::decode(instructions)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|offset| {
+            corrupt(format!(
+                "{} at offset {}",
+                code::invalid(instructions, offset),
+                offset
+            ))
+        })?;
+
+    for instruction in &decoded {
+        let (offset, op) = (instruction.offset, instruction.op);
+        let operand = instruction.operands.first().copied().unwrap_or(0);
+
+        let valid = match op {
+            Opcode::Constant => operand < bytecode.constants.len(),
+            Opcode::Closure => match bytecode.constants.get(operand) {
+                Some(Object::CompiledFunction(closure)) => {
+                    closure.free.iter().all(|free| match free.capture {
+                        Capture::Local(slot) => function.cells.contains(&slot),
+                        Capture::Free(index) => index < function.free.len(),
+                    })
+                }
+                _ => false,
+            },
+            Opcode::Jump | Opcode::JumpNotTruthy => {
+                operand == instructions.len()
+                    || decoded
+                        .binary_search_by_key(&operand, |instruction| instruction.offset)
+                        .is_ok()
+            }
+            Opcode::GetGlobal | Opcode::SetGlobal => operand < bytecode.globals.len(),
+            Opcode::GetLocal | Opcode::SetLocal => operand < function.locals.len(),
+            Opcode::GetCell | Opcode::SetCell => function.cells.contains(&operand),
+            Opcode::GetFree => operand < function.free.len(),
+            Opcode::GetBuiltin => operand < builtins::names().count(),
+            _ => true,
+        };
+
+        if !valid {
+            return Err(corrupt(format!(
+                "{} at offset {} has an invalid operand {}",
+                op, offset, operand
+            )));
+        }
+    }
+
+    if function
+        .cells
+        .iter()
+        .any(|slot| *slot >= function.locals.len())
+    {
+        return Err(corrupt(
+            "a function has a cell for a local it does not have",
+        ));
+    }
+
+    let ordered = function
+        .positions
+        .windows(2)
+        .all(|pair| pair[0].0 < pair[1].0);
+    let in_bounds = function
+        .positions
+        .last()
+        .is_none_or(|(offset, _)| *offset < instructions.len());
+    if !ordered || !in_bounds {
+        return Err(corrupt("the debug table does not match the instructions"));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::code::Instructions;
+    use crate::compiler;
+    use crate::lexer;
+    use crate::parser;
+    use crate::vm::Vm;
+
+    const PROGRAM: &str = "\
+let greeting = \"hello\";
+let adder = fn(a) { fn(b) { a + b } };
+let result = adder(40)(2);
+if (result > 41) { [greeting, result, result / 8.0] } else { false }";
+
+    fn compiled(input: &str) -> Bytecode {
+        let mut parser = parser::Parser::new(lexer::Lexer::new(input));
+        let program = parser.parse_program();
+        assert!(parser.errors.is_empty(), "{:?}", parser.errors);
+        compiler::compile(&program).unwrap()
+    }
+
+    fn file(bytecode: Bytecode) -> File {
+        File {
+            bytecode,
+            source: Some(Source::new("test.mk", PROGRAM)),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let file = file(compiled(PROGRAM));
+
+        let decoded = decode(&encode(&file)).unwrap();
+        assert_eq!(file, decoded);
+        assert_eq!(
+            "[hello, 42, 5.25]",
+            Vm::new(decoded.bytecode).run().to_string()
+        );
+    }
+
+    #[test]
+    fn test_round_trip_without_debug_table() {
+        let mut file = file(compiled(PROGRAM));
+        file.source = None;
+
+        let decoded = decode(&encode(&file)).unwrap();
+        assert!(decoded.bytecode.main.positions.is_empty());
+        assert_eq!(None, decoded.source);
+        assert_eq!(
+            "[hello, 42, 5.25]",
+            Vm::new(decoded.bytecode).run().to_string()
+        );
+    }
+
+    #[test]
+    fn test_source_matches_only_its_text() {
+        let source = Source::new("test.mk", PROGRAM);
+        assert!(source.matches(PROGRAM));
+        assert!(!source.matches(&PROGRAM.replace("40", "41")));
+        assert!(!source.matches(""));
+    }
+
+    #[test]
+    fn test_rejects_other_files() {
+        assert_eq!(Err(DecodeError::NotBytecode), decode(b"let x = 1;"));
+
+        let mut bytes = encode(&file(compiled("1")));
+        bytes[5] += 1;
+        assert_eq!(
+            Err(DecodeError::UnsupportedVersion(VERSION + 1)),
+            decode(&bytes)
+        );
+
+        // Version 1 had no float constants and laid functions out differently
+        bytes[4..6].copy_from_slice(&1_u16.to_be_bytes());
+        assert_eq!(Err(DecodeError::UnsupportedVersion(1)), decode(&bytes));
+    }
+
+    #[test]
+    fn test_rejects_truncated_files() {
+        let bytes = encode(&file(compiled(PROGRAM)));
+
+        for len in MAGIC.len()..bytes.len() {
+            assert_eq!(
+                Err(DecodeError::Truncated),
+                decode(&bytes[..len]),
+                "length: {}",
+                len
+            );
+        }
+
+        let mut bytes = bytes;
+        bytes.push(0);
+        assert!(matches!(decode(&bytes), Err(DecodeError::Corrupt(_))));
+    }
+
+    #[test]
+    fn test_rejects_invalid_instructions() {
+        let make = |parts: &[(Opcode, &[usize])]| -> Instructions {
+            parts
+                .iter()
+                .flat_map(|(op, operands)| code::make(*op, operands).unwrap())
+                .collect()
+        };
+
+        let cases = [
+            vec![255],
+            make(&[(Opcode::Constant, &[1])]),
+            make(&[(Opcode::Closure, &[0])]),
+            make(&[(Opcode::GetGlobal, &[0])]),
+            make(&[(Opcode::GetLocal, &[0])]),
+            make(&[(Opcode::GetFree, &[0])]),
+            make(&[(Opcode::GetCell, &[0])]),
+            make(&[(Opcode::GetBuiltin, &[200])]),
+            make(&[(Opcode::Constant, &[0]), (Opcode::Jump, &[1])]),
+            make(&[(Opcode::Constant, &[0])])[..2].to_vec(),
+        ];
+
+        for instructions in cases {
+            let mut bytecode = compiled("1");
+            bytecode.main.instructions = instructions.clone();
+            bytecode.main.positions.clear();
+
+            let result = decode(&encode(&file(bytecode)));
+            assert!(
+                matches!(result, Err(DecodeError::Corrupt(_))),
+                "instructions: {:?}, result: {:?}",
+                instructions,
+                result
+            );
+        }
+    }
+}
@@ -0,0 +1,503 @@
+use core::fmt;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+use num_bigint::BigInt;
+use num_traits::ToPrimitive;
+
+use crate::ast;
+use crate::builtins::Builtin;
+use crate::code::Instructions;
+use crate::diagnostic::{self, Diagnostic};
+use crate::environment::Environment;
+use crate::token;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Object {
+    Integer(i64),
+    /// An integer outside the range of `i64`, made by arithmetic that overflows in
+    /// `IntegerMode::BigInt`.  It behaves just like an `Integer` in every other way
+    BigInt(BigInt),
+    Float(f64),
+    Boolean(bool),
+    String(String),
+    Array(Vec<Object>),
+    /// Ordered by key so that a hash always prints the same way
+    Hash(BTreeMap<HashKey, Object>),
+    Null,
+    /// Wraps the value of a `return` statement while it unwinds to the enclosing
+    /// function or program
+    ReturnValue(Box<Object>),
+    /// A runtime error raised by the expression at `span`.  Errors unwind like return
+    /// values but are never unwrapped
+    Error {
+        message: String,
+        span: token::Span,
+    },
+    Function(Function),
+    Builtin(Builtin),
+    /// A function compiled to bytecode, as found in the constants pool
+    CompiledFunction(Rc<CompiledFunction>),
+    /// A compiled function together with the variables it captured, which is what a
+    /// function value is in the virtual machine
+    Closure(Rc<Closure>),
+}
+
+impl Object {
+    #[must_use]
+    pub const fn type_name(&self) -> &'static str {
+        match self {
+            Self::Integer(_) | Self::BigInt(_) => "INTEGER",
+            Self::Float(_) => "FLOAT",
+            Self::Boolean(_) => "BOOLEAN",
+            Self::String(_) => "STRING",
+            Self::Array(_) => "ARRAY",
+            Self::Hash(_) => "HASH",
+            Self::Null => "NULL",
+            Self::ReturnValue(_) => "RETURN_VALUE",
+            Self::Error { .. } => "ERROR",
+            Self::Function(_) => "FUNCTION",
+            Self::Builtin(_) => "BUILTIN",
+            Self::CompiledFunction(_) => "COMPILED_FUNCTION",
+            // Reported like the evaluator's functions so both give the same errors
+            Self::Closure(_) => "FUNCTION",
+        }
+    }
+
+    /// Everything except `false` and `null` is truthy
+    #[must_use]
+    pub const fn is_truthy(&self) -> bool {
+        !matches!(self, Self::Boolean(false) | Self::Null)
+    }
+
+    /// The key this value is stored under in a hash, or `None` if it cannot be a key.
+    /// Floats cannot be keys, as `NaN` is not equal to itself
+    #[must_use]
+    pub fn hash_key(&self) -> Option<HashKey> {
+        match self {
+            Self::Integer(value) => Some(HashKey::Integer(*value)),
+            Self::BigInt(value) => Some(HashKey::BigInt(value.clone())),
+            Self::Boolean(value) => Some(HashKey::Boolean(*value)),
+            Self::String(value) => Some(HashKey::String(value.clone())),
+            _ => None,
+        }
+    }
+
+    /// The value of a number as a float, or `None` for any other value.  Integers
+    /// are rounded to the nearest float, which is infinite for the very largest
+    #[must_use]
+    pub fn to_float(&self) -> Option<f64> {
+        match self {
+            Self::Integer(value) => Some(*value as f64),
+            Self::BigInt(value) => value.to_f64(),
+            Self::Float(value) => Some(*value),
+            _ => None,
+        }
+    }
+
+    const fn type_error(&self, expected: &'static str) -> TypeError {
+        TypeError {
+            expected,
+            found: self.type_name(),
+        }
+    }
+
+    #[must_use]
+    pub const fn error(message: String, span: token::Span) -> Self {
+        Self::Error { message, span }
+    }
+
+    #[must_use]
+    pub const fn is_error(&self) -> bool {
+        matches!(self, Self::Error { .. })
+    }
+
+    /// The diagnostic to show for a runtime error, or `None` for any other value
+    #[must_use]
+    pub fn to_diagnostic(&self) -> Option<Diagnostic> {
+        match self {
+            Self::Error { message, span } => Some(Diagnostic::error(
+                diagnostic::RUNTIME_ERROR,
+                message.clone(),
+                *span,
+            )),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Object {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Integer(value) => write!(f, "{}", value),
+            Self::BigInt(value) => write!(f, "{}", value),
+            // Debug always keeps a decimal point or exponent, so `1.0` does not
+            // print like the integer `1`
+            Self::Float(value) => write!(f, "{:?}", value),
+            Self::Boolean(value) => write!(f, "{}", value),
+            Self::String(value) => write!(f, "{}", value),
+            Self::Array(elements) => {
+                let elements: Vec<String> = elements.iter().map(ToString::to_string).collect();
+                write!(f, "[{}]", elements.join(", "))
+            }
+            Self::Hash(pairs) => {
+                let pairs: Vec<String> = pairs
+                    .iter()
+                    .map(|(key, value)| format!("{}: {}", key, value))
+                    .collect();
+                write!(f, "{{{}}}", pairs.join(", "))
+            }
+            Self::Null => write!(f, "null"),
+            Self::ReturnValue(value) => write!(f, "{}", value),
+            Self::Error { message, span } => write!(f, "ERROR: {}: {}", span, message),
+            Self::Function(function) => write!(f, "{}", function),
+            Self::Builtin(builtin) => write!(f, "{}", builtin),
+            Self::CompiledFunction(function) => write!(f, "{}", function),
+            Self::Closure(closure) => write!(f, "{}", closure.function),
+        }
+    }
+}
+
+// Conversions between Rust values and Monkey objects, for host programs that pass
+// values in and out of a script
+
+impl From<i64> for Object {
+    fn from(value: i64) -> Self {
+        Self::Integer(value)
+    }
+}
+
+impl From<i32> for Object {
+    fn from(value: i32) -> Self {
+        Self::Integer(value.into())
+    }
+}
+
+/// A value that fits in an `i64` becomes an ordinary `Integer`
+impl From<BigInt> for Object {
+    fn from(value: BigInt) -> Self {
+        i64::try_from(&value).map_or_else(|_| Self::BigInt(value), Self::Integer)
+    }
+}
+
+impl From<f64> for Object {
+    fn from(value: f64) -> Self {
+        Self::Float(value)
+    }
+}
+
+impl From<bool> for Object {
+    fn from(value: bool) -> Self {
+        Self::Boolean(value)
+    }
+}
+
+impl From<String> for Object {
+    fn from(value: String) -> Self {
+        Self::String(value)
+    }
+}
+
+impl From<&str> for Object {
+    fn from(value: &str) -> Self {
+        Self::String(value.to_owned())
+    }
+}
+
+impl<T: Into<Self>> From<Vec<T>> for Object {
+    fn from(values: Vec<T>) -> Self {
+        Self::Array(values.into_iter().map(Into::into).collect())
+    }
+}
+
+impl<T: Into<Self>> From<HashMap<String, T>> for Object {
+    fn from(values: HashMap<String, T>) -> Self {
+        Self::Hash(
+            values
+                .into_iter()
+                .map(|(key, value)| (HashKey::String(key), value.into()))
+                .collect(),
+        )
+    }
+}
+
+/// `None` becomes `null`
+impl<T: Into<Self>> From<Option<T>> for Object {
+    fn from(value: Option<T>) -> Self {
+        value.map_or(Self::Null, Into::into)
+    }
+}
+
+/// An object was not of the type a conversion to Rust expected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TypeError {
+    pub expected: &'static str,
+    pub found: &'static str,
+}
+
+impl fmt::Display for TypeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "expected {}, got {}", self.expected, self.found)
+    }
+}
+
+impl std::error::Error for TypeError {}
+
+impl TryFrom<Object> for i64 {
+    type Error = TypeError;
+
+    fn try_from(object: Object) -> Result<Self, Self::Error> {
+        match object {
+            Object::Integer(value) => Ok(value),
+            Object::BigInt(_) => Err(TypeError {
+                expected: "INTEGER in the range of i64",
+                found: "INTEGER",
+            }),
+            other => Err(other.type_error("INTEGER")),
+        }
+    }
+}
+
+impl TryFrom<Object> for BigInt {
+    type Error = TypeError;
+
+    fn try_from(object: Object) -> Result<Self, Self::Error> {
+        match object {
+            Object::Integer(value) => Ok(value.into()),
+            Object::BigInt(value) => Ok(value),
+            other => Err(other.type_error("INTEGER")),
+        }
+    }
+}
+
+/// An integer converts to the nearest float
+impl TryFrom<Object> for f64 {
+    type Error = TypeError;
+
+    fn try_from(object: Object) -> Result<Self, Self::Error> {
+        object.to_float().ok_or_else(|| object.type_error("FLOAT"))
+    }
+}
+
+impl TryFrom<Object> for bool {
+    type Error = TypeError;
+
+    fn try_from(object: Object) -> Result<Self, Self::Error> {
+        match object {
+            Object::Boolean(value) => Ok(value),
+            other => Err(other.type_error("BOOLEAN")),
+        }
+    }
+}
+
+impl TryFrom<Object> for String {
+    type Error = TypeError;
+
+    fn try_from(object: Object) -> Result<Self, Self::Error> {
+        match object {
+            Object::String(value) => Ok(value),
+            other => Err(other.type_error("STRING")),
+        }
+    }
+}
+
+// Lets `Object` itself be the element type below, as converting to it cannot fail
+impl From<core::convert::Infallible> for TypeError {
+    fn from(never: core::convert::Infallible) -> Self {
+        match never {}
+    }
+}
+
+impl<T> TryFrom<Object> for Vec<T>
+where
+    T: TryFrom<Object>,
+    T::Error: Into<TypeError>,
+{
+    type Error = TypeError;
+
+    fn try_from(object: Object) -> Result<Self, Self::Error> {
+        match object {
+            Object::Array(elements) => elements
+                .into_iter()
+                .map(|element| T::try_from(element).map_err(Into::into))
+                .collect(),
+            other => Err(other.type_error("ARRAY")),
+        }
+    }
+}
+
+/// Only hashes whose keys are all strings convert
+impl<T> TryFrom<Object> for HashMap<String, T>
+where
+    T: TryFrom<Object>,
+    T::Error: Into<TypeError>,
+{
+    type Error = TypeError;
+
+    fn try_from(object: Object) -> Result<Self, Self::Error> {
+        match object {
+            Object::Hash(pairs) => pairs
+                .into_iter()
+                .map(|(key, value)| match key {
+                    HashKey::String(key) => Ok((key, T::try_from(value).map_err(Into::into)?)),
+                    other => Err(Object::from(other).type_error("STRING")),
+                })
+                .collect(),
+            other => Err(other.type_error("HASH")),
+        }
+    }
+}
+
+impl From<HashKey> for Object {
+    fn from(key: HashKey) -> Self {
+        match key {
+            HashKey::Integer(value) => Self::Integer(value),
+            HashKey::BigInt(value) => Self::BigInt(value),
+            HashKey::Boolean(value) => Self::Boolean(value),
+            HashKey::String(value) => Self::String(value),
+        }
+    }
+}
+
+/// The values that can be used as hash keys.  Functions, arrays and hashes cannot
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum HashKey {
+    Integer(i64),
+    BigInt(BigInt),
+    Boolean(bool),
+    String(String),
+}
+
+impl fmt::Display for HashKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Integer(value) => write!(f, "{}", value),
+            Self::BigInt(value) => write!(f, "{}", value),
+            Self::Boolean(value) => write!(f, "{}", value),
+            Self::String(value) => write!(f, "{}", value),
+        }
+    }
+}
+
+/// A function value closes over the environment it was defined in
+#[derive(Clone)]
+pub struct Function {
+    pub parameters: Vec<ast::Identifier>,
+    pub body: ast::BlockStatement,
+    pub env: Rc<RefCell<Environment>>,
+}
+
+// A function bound with `let` is stored in the environment it captures, so the
+// captured environment is compared by identity and left out of `Debug` to avoid
+// recursing through that cycle
+impl PartialEq for Function {
+    fn eq(&self, other: &Self) -> bool {
+        self.parameters == other.parameters
+            && self.body == other.body
+            && Rc::ptr_eq(&self.env, &other.env)
+    }
+}
+
+impl Eq for Function {}
+
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Function")
+            .field("parameters", &self.parameters)
+            .field("body", &self.body)
+            .finish_non_exhaustive()
+    }
+}
+
+impl fmt::Display for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let parameters: Vec<&str> = self
+            .parameters
+            .iter()
+            .map(|parameter| parameter.value.as_str())
+            .collect();
+        write!(f, "fn({}) {{ ... }}", parameters.join(", "))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CompiledFunction {
+    pub instructions: Instructions,
+    pub parameters: Vec<String>,
+    /// The name of each local slot: the parameters, then every other name the body
+    /// binds with `let`
+    pub locals: Vec<String>,
+    /// The slots of the locals kept in cells, in order
+    pub cells: Vec<usize>,
+    /// The locals of enclosing functions the body refers to, indexed by `OpGetFree`
+    pub free: Vec<FreeVariable>,
+    /// Where each instruction came from, as pairs of instruction offset and span
+    /// ordered by offset.  An instruction that can raise a runtime error has the
+    /// span of its expression, and any other the span of its statement
+    pub positions: Vec<(usize, token::Span)>,
+}
+
+impl CompiledFunction {
+    /// The span of the instruction at `offset`, or an empty span if none was recorded
+    #[must_use]
+    pub fn span_at(&self, offset: usize) -> token::Span {
+        self.positions
+            .binary_search_by_key(&offset, |(offset, _)| *offset)
+            .map_or_else(|_| token::Span::default(), |i| self.positions[i].1)
+    }
+}
+
+impl fmt::Display for CompiledFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "fn({}) {{ ... }}", self.parameters.join(", "))
+    }
+}
+
+/// A local of an enclosing function that a compiled function refers to
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FreeVariable {
+    pub name: String,
+    /// Where the closure finds the local when it is made
+    pub capture: Capture,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capture {
+    /// The cell of the local in this slot of the function the closure is made in
+    Local(usize),
+    /// A free variable of the closure running when this one is made, at this index
+    Free(usize),
+}
+
+/// A local kept apart from the stack so it can outlive its call in the closures
+/// that capture it, which see every later change to it.  `None` until it is set
+pub type LocalCell = Rc<RefCell<Option<Object>>>;
+
+#[derive(Clone)]
+pub struct Closure {
+    pub function: Rc<CompiledFunction>,
+    /// The cells of the function's free variables, in order
+    pub free: Vec<LocalCell>,
+}
+
+// As with `Function`, a closure bound to a local is stored in a cell it captures
+impl PartialEq for Closure {
+    fn eq(&self, other: &Self) -> bool {
+        self.function == other.function
+            && self.free.len() == other.free.len()
+            && self
+                .free
+                .iter()
+                .zip(&other.free)
+                .all(|(a, b)| Rc::ptr_eq(a, b))
+    }
+}
+
+impl fmt::Debug for Closure {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Closure")
+            .field("function", &self.function)
+            .finish_non_exhaustive()
+    }
+}
@@ -1,138 +1,680 @@
 use crate::ast;
+use crate::diagnostic::{self, Diagnostic};
 use crate::lexer;
-use crate::token;
+use crate::token::{self, TokenKind};
 use std::collections::hash_map::HashMap;
+use std::rc::Rc;
 
+/// How deeply expressions may nest, counting every bracket, prefix operator and
+/// block inside one another.  Everything that walks the tree recurses for each
+/// level, so this keeps hostile input from overflowing the stack
+pub const MAX_NESTING_DEPTH: usize = 256;
+
+/// Free stack the parser keeps below it before each nested expression, and how
+/// much more to allocate when there is less.  A level takes several kilobytes in
+/// debug builds, more than a small thread stack has room for at the limit
+const STACK_RED_ZONE: usize = 64 * 1024;
+const STACK_GROWTH: usize = 1024 * 1024;
+
+/// Binding power of an operator.  Variants are ordered from weakest to strongest so
+/// they can be compared directly while climbing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Precedence {
+    Lowest,
+    /// `==` and `!=`
+    Equals,
+    /// `<` and `>`
+    LessGreater,
+    /// `+` and `-`
+    Sum,
+    /// `*` and `/`
+    Product,
+    /// `!x` and `-x`
+    Prefix,
+    /// `function(args)`
+    Call,
+    /// `array[index]`
+    Index,
+}
+
+impl Precedence {
+    fn of(kind: TokenKind) -> Self {
+        match kind {
+            TokenKind::Eq | TokenKind::NotEq => Self::Equals,
+            TokenKind::Lt | TokenKind::Gt => Self::LessGreater,
+            TokenKind::Plus | TokenKind::Minus => Self::Sum,
+            TokenKind::Asterisk | TokenKind::Slash => Self::Product,
+            TokenKind::LParen => Self::Call,
+            TokenKind::LBracket => Self::Index,
+            _ => Self::Lowest,
+        }
+    }
+}
+
+/// Parses an expression that begins with the current token
 pub trait Prefix {
-    fn parse(&self) -> Result<ast::Expression, anyhow::Error>;
+    fn parse(&self, parser: &mut Parser) -> Result<ast::Expression, Diagnostic>;
 }
 
+/// Parses an expression whose operator is the current token, given the expression
+/// already parsed to its left
 pub trait Infix {
-    fn parse(&self, expression: ast::Expression) -> Result<ast::Expression, anyhow::Error>;
+    fn parse(
+        &self,
+        parser: &mut Parser,
+        expression: ast::Expression,
+    ) -> Result<ast::Expression, Diagnostic>;
+}
+
+struct IdentifierParser;
+
+impl Prefix for IdentifierParser {
+    fn parse(&self, parser: &mut Parser) -> Result<ast::Expression, Diagnostic> {
+        Ok(ast::Expression::Identifier(parser.current_token.clone()))
+    }
+}
+
+struct IntegerParser;
+
+impl Prefix for IntegerParser {
+    fn parse(&self, parser: &mut Parser) -> Result<ast::Expression, Diagnostic> {
+        Ok(ast::Expression::Integer(parser.current_token.clone()))
+    }
+}
+
+struct FloatParser;
+
+impl Prefix for FloatParser {
+    fn parse(&self, parser: &mut Parser) -> Result<ast::Expression, Diagnostic> {
+        Ok(ast::Expression::Float(parser.current_token.clone()))
+    }
+}
+
+struct StringParser;
+
+impl Prefix for StringParser {
+    fn parse(&self, parser: &mut Parser) -> Result<ast::Expression, Diagnostic> {
+        Ok(ast::Expression::StringLiteral(parser.current_token.clone()))
+    }
+}
+
+struct BooleanParser;
+
+impl Prefix for BooleanParser {
+    fn parse(&self, parser: &mut Parser) -> Result<ast::Expression, Diagnostic> {
+        Ok(ast::Expression::Boolean(parser.current_token.clone()))
+    }
+}
+
+struct PrefixOperatorParser;
+
+impl Prefix for PrefixOperatorParser {
+    fn parse(&self, parser: &mut Parser) -> Result<ast::Expression, Diagnostic> {
+        let token = parser.current_token.clone();
+        parser.next_token();
+        let right = parser.parse_expression(Precedence::Prefix)?;
+
+        Ok(ast::Expression::Prefix {
+            token,
+            right: Box::new(right),
+        })
+    }
+}
+
+struct GroupedParser;
+
+impl Prefix for GroupedParser {
+    fn parse(&self, parser: &mut Parser) -> Result<ast::Expression, Diagnostic> {
+        parser.next_token();
+        let expression = parser.parse_expression(Precedence::Lowest)?;
+
+        parser.expect_peek(TokenKind::RParen)?;
+
+        Ok(expression)
+    }
+}
+
+struct IfParser;
+
+impl Prefix for IfParser {
+    fn parse(&self, parser: &mut Parser) -> Result<ast::Expression, Diagnostic> {
+        let mut token = parser.current_token.clone();
+
+        parser.expect_peek(TokenKind::LParen)?;
+        parser.next_token();
+        let condition = parser.parse_expression(Precedence::Lowest)?;
+
+        parser.expect_peek(TokenKind::RParen)?;
+        parser.expect_peek(TokenKind::LBrace)?;
+        let consequence = parser.parse_block_statement()?;
+
+        let alternative = if parser.peek_token_is(TokenKind::Else) {
+            parser.next_token();
+            parser.expect_peek(TokenKind::LBrace)?;
+            Some(parser.parse_block_statement()?)
+        } else {
+            None
+        };
+        token.span = token.span.to(parser.current_token.span);
+
+        Ok(ast::Expression::If {
+            token,
+            condition: Box::new(condition),
+            consequence,
+            alternative,
+        })
+    }
+}
+
+struct FunctionLiteralParser;
+
+impl Prefix for FunctionLiteralParser {
+    fn parse(&self, parser: &mut Parser) -> Result<ast::Expression, Diagnostic> {
+        let mut token = parser.current_token.clone();
+
+        parser.expect_peek(TokenKind::LParen)?;
+        let params = parser.parse_function_parameters()?;
+
+        parser.expect_peek(TokenKind::LBrace)?;
+        let body = parser.parse_block_statement()?;
+        token.span = token.span.to(parser.current_token.span);
+
+        Ok(ast::Expression::FunctionLiteral {
+            token,
+            params,
+            body,
+        })
+    }
+}
+
+struct InfixOperatorParser;
+
+impl Infix for InfixOperatorParser {
+    fn parse(
+        &self,
+        parser: &mut Parser,
+        expression: ast::Expression,
+    ) -> Result<ast::Expression, Diagnostic> {
+        let token = parser.current_token.clone();
+        let precedence = parser.current_precedence();
+        parser.next_token();
+        let right = parser.parse_expression(precedence)?;
+
+        Ok(ast::Expression::Infix {
+            token,
+            left: Box::new(expression),
+            right: Box::new(right),
+        })
+    }
+}
+
+struct CallParser;
+
+impl Infix for CallParser {
+    fn parse(
+        &self,
+        parser: &mut Parser,
+        expression: ast::Expression,
+    ) -> Result<ast::Expression, Diagnostic> {
+        let mut token = parser.current_token.clone();
+        let args = parser.parse_expression_list(TokenKind::RParen)?;
+        token.span = token.span.to(parser.current_token.span);
+
+        Ok(ast::Expression::Call {
+            token,
+            function: Box::new(expression),
+            args,
+        })
+    }
 }
 
-pub struct Parser {
-    lexer: lexer::Lexer,
+struct ArrayLiteralParser;
+
+impl Prefix for ArrayLiteralParser {
+    fn parse(&self, parser: &mut Parser) -> Result<ast::Expression, Diagnostic> {
+        let mut token = parser.current_token.clone();
+        let elements = parser.parse_expression_list(TokenKind::RBracket)?;
+        token.span = token.span.to(parser.current_token.span);
+
+        Ok(ast::Expression::ArrayLiteral { token, elements })
+    }
+}
+
+/// A `{` in expression position always starts a hash, as blocks are only parsed
+/// where the grammar expects one, e.g. after `if (...)` or `fn(...)`
+struct HashLiteralParser;
+
+impl Prefix for HashLiteralParser {
+    fn parse(&self, parser: &mut Parser) -> Result<ast::Expression, Diagnostic> {
+        let mut token = parser.current_token.clone();
+        let mut pairs = Vec::new();
+
+        if !parser.peek_token_is(TokenKind::RBrace) {
+            loop {
+                parser.next_token();
+                let key = parser.parse_expression(Precedence::Lowest)?;
+                parser.expect_peek(TokenKind::Colon)?;
+                parser.next_token();
+                let value = parser.parse_expression(Precedence::Lowest)?;
+                pairs.push((key, value));
+
+                if !parser.peek_token_is(TokenKind::Comma) {
+                    break;
+                }
+                parser.next_token();
+            }
+        }
+
+        parser.expect_peek(TokenKind::RBrace)?;
+        token.span = token.span.to(parser.current_token.span);
+
+        Ok(ast::Expression::HashLiteral { token, pairs })
+    }
+}
+
+struct IndexParser;
+
+impl Infix for IndexParser {
+    fn parse(
+        &self,
+        parser: &mut Parser,
+        expression: ast::Expression,
+    ) -> Result<ast::Expression, Diagnostic> {
+        let mut token = parser.current_token.clone();
+        parser.next_token();
+        let index = parser.parse_expression(Precedence::Lowest)?;
+        parser.expect_peek(TokenKind::RBracket)?;
+        token.span = token.span.to(parser.current_token.span);
+
+        Ok(ast::Expression::Index {
+            token,
+            left: Box::new(expression),
+            index: Box::new(index),
+        })
+    }
+}
+
+pub struct Parser<'a> {
+    lexer: lexer::Lexer<'a>,
     current_token: token::Token,
     peek_token: token::Token,
-    pub errors: Vec<String>,
-    prefix_parse_fns: HashMap<String, Box<dyn Prefix>>,
-    infix_parse_fns: HashMap<String, Box<dyn Infix>>,
+    pub errors: Vec<Diagnostic>,
+    /// Where the lexer rejected a token and an `ILLEGAL` token stands in for it
+    rejected: Vec<token::Span>,
+    depth: usize,
+    prefix_parse_fns: HashMap<TokenKind, Rc<dyn Prefix>>,
+    infix_parse_fns: HashMap<TokenKind, Rc<dyn Infix>>,
 }
 
-impl Parser {
-    pub fn new(lexer: lexer::Lexer) -> Result<Self, anyhow::Error> {
+impl<'a> Parser<'a> {
+    #[must_use]
+    pub fn new(lexer: lexer::Lexer<'a>) -> Self {
         let mut me = Self {
             lexer,
-            current_token: token::Token::new(token::ILLEGAL, ""),
-            peek_token: token::Token::new(token::ILLEGAL, ""),
+            current_token: token::Token::new(TokenKind::Illegal, ""),
+            peek_token: token::Token::new(TokenKind::Illegal, ""),
             errors: Vec::default(),
+            rejected: Vec::new(),
+            depth: 0,
             prefix_parse_fns: HashMap::new(),
             infix_parse_fns: HashMap::new(),
         };
 
-        me.next_token()?;
-        me.next_token()?;
+        me.register_prefix(TokenKind::Ident, Box::new(IdentifierParser));
+        me.register_prefix(TokenKind::Int, Box::new(IntegerParser));
+        me.register_prefix(TokenKind::Float, Box::new(FloatParser));
+        me.register_prefix(TokenKind::String, Box::new(StringParser));
+        me.register_prefix(TokenKind::True, Box::new(BooleanParser));
+        me.register_prefix(TokenKind::False, Box::new(BooleanParser));
+        me.register_prefix(TokenKind::Bang, Box::new(PrefixOperatorParser));
+        me.register_prefix(TokenKind::Minus, Box::new(PrefixOperatorParser));
+        me.register_prefix(TokenKind::LParen, Box::new(GroupedParser));
+        me.register_prefix(TokenKind::If, Box::new(IfParser));
+        me.register_prefix(TokenKind::Function, Box::new(FunctionLiteralParser));
+        me.register_prefix(TokenKind::LBracket, Box::new(ArrayLiteralParser));
+        me.register_prefix(TokenKind::LBrace, Box::new(HashLiteralParser));
+
+        for operator in [
+            TokenKind::Plus,
+            TokenKind::Minus,
+            TokenKind::Asterisk,
+            TokenKind::Slash,
+            TokenKind::Eq,
+            TokenKind::NotEq,
+            TokenKind::Lt,
+            TokenKind::Gt,
+        ] {
+            me.register_infix(operator, Box::new(InfixOperatorParser));
+        }
+        me.register_infix(TokenKind::LParen, Box::new(CallParser));
+        me.register_infix(TokenKind::LBracket, Box::new(IndexParser));
+
+        me.next_token();
+        me.next_token();
 
-        Ok(me)
+        me
     }
 
-    pub fn register_prefix(&mut self, token_type: &str, prefix_fn: Box<dyn Prefix>) {
-        self.prefix_parse_fns
-            .insert(token_type.to_owned(), prefix_fn);
+    pub fn register_prefix(&mut self, kind: TokenKind, prefix_fn: Box<dyn Prefix>) {
+        self.prefix_parse_fns.insert(kind, Rc::from(prefix_fn));
     }
 
-    pub fn register_infix(&mut self, token_type: &str, infix_fn: Box<dyn Infix>) {
-        self.infix_parse_fns.insert(token_type.to_owned(), infix_fn);
+    pub fn register_infix(&mut self, kind: TokenKind, infix_fn: Box<dyn Infix>) {
+        self.infix_parse_fns.insert(kind, Rc::from(infix_fn));
     }
 
-    pub fn next_token(&mut self) -> Result<(), anyhow::Error> {
+    /// Advances by one token.  A token the lexer cannot read is reported and replaced
+    /// by an `ILLEGAL` token, so parsing carries on and can report later errors too
+    pub fn next_token(&mut self) {
         self.current_token = self.peek_token.clone();
-        self.peek_token = self.lexer.next_token()?;
-
-        Ok(())
+        self.peek_token = match self.lexer.next_token() {
+            Ok(token) => token,
+            Err(err) => {
+                let span = err.span;
+                self.report(err);
+                self.rejected.push(span);
+                token::Token::new(TokenKind::Illegal, "").with_span(span)
+            }
+        };
     }
 
-    pub fn parse_program(&mut self) -> Result<ast::Program, anyhow::Error> {
+    /// Parses the whole input.  A statement that fails to parse is reported in
+    /// `errors` and skipped, so the returned program holds every statement that did
+    /// parse and `errors` holds every problem found, in source order
+    pub fn parse_program(&mut self) -> ast::Program {
         let mut program = ast::Program::default();
 
-        while self.current_token.token_type != token::EOF {
-            let statement = self.parse_statement()?;
-            program.statements.push(statement);
+        while !self.current_token_is(TokenKind::Eof) {
+            match self.parse_statement() {
+                Ok(statement) => program.statements.push(statement),
+                // Whatever follows the limit is the rest of the nesting, which would
+                // only be reported again token by token
+                Err(err) if err.code == diagnostic::TOO_DEEPLY_NESTED => {
+                    self.report(err);
+                    break;
+                }
+                Err(err) => {
+                    self.report(err);
+                    self.synchronize();
+                }
+            }
 
-            self.next_token()?;
+            self.next_token();
         }
 
-        Ok(program)
+        self.errors.sort_by_key(|err| err.span.start);
+
+        program
     }
 
-    fn parse_statement(&mut self) -> Result<ast::Statement, anyhow::Error> {
-        match self.current_token.token_type.as_str() {
-            token::LET => {
-                return self.parse_let_statement();
-            }
-            token::RETURN => {
-                return self.parse_return_statement();
+    fn parse_statement(&mut self) -> Result<ast::Statement, Diagnostic> {
+        match self.current_token.kind {
+            TokenKind::Let => self.parse_let_statement(),
+            TokenKind::Return => self.parse_return_statement(),
+            _ => self.parse_expression_statement(),
+        }
+    }
+
+    fn parse_let_statement(&mut self) -> Result<ast::Statement, Diagnostic> {
+        let token = self.current_token.clone();
+        let let_span = token.span;
+
+        self.expect_peek(TokenKind::Ident)
+            .map_err(|err| err.with_label(let_span, "in this let statement"))?;
+
+        let ident = ast::Identifier::new(self.current_token.clone(), self.current_token.literal());
+
+        self.expect_peek(TokenKind::Assign)
+            .map_err(|err| err.with_label(let_span, "in this let statement"))?;
+
+        self.next_token();
+        let expression = self.parse_expression(Precedence::Lowest)?;
+
+        if self.peek_token_is(TokenKind::Semicolon) {
+            self.next_token();
+        }
+
+        Ok(ast::Statement::Let(token, ident, expression))
+    }
+
+    fn parse_return_statement(&mut self) -> Result<ast::Statement, Diagnostic> {
+        let token = self.current_token.clone();
+        self.next_token();
+        let expression = self.parse_expression(Precedence::Lowest)?;
+
+        if self.peek_token_is(TokenKind::Semicolon) {
+            self.next_token();
+        }
+
+        Ok(ast::Statement::Return(token, expression))
+    }
+
+    fn parse_expression_statement(&mut self) -> Result<ast::Statement, Diagnostic> {
+        let token = self.current_token.clone();
+        let expression = self.parse_expression(Precedence::Lowest)?;
+
+        if self.peek_token_is(TokenKind::Semicolon) {
+            self.next_token();
+        }
+
+        Ok(ast::Statement::Expression(token, expression))
+    }
+
+    /// Parses an expression starting at the current token, consuming operators for as
+    /// long as they bind tighter than `precedence`.  On return the current token is
+    /// the last token of the expression
+    fn parse_expression(&mut self, precedence: Precedence) -> Result<ast::Expression, Diagnostic> {
+        if self.depth == MAX_NESTING_DEPTH {
+            return Err(Diagnostic::error(
+                diagnostic::TOO_DEEPLY_NESTED,
+                format!("expression nested more than {} deep", MAX_NESTING_DEPTH),
+                self.current_token.span,
+            ));
+        }
+
+        self.depth += 1;
+        let expression = stacker::maybe_grow(STACK_RED_ZONE, STACK_GROWTH, || {
+            self.parse_nested_expression(precedence)
+        });
+        self.depth -= 1;
+
+        expression
+    }
+
+    fn parse_nested_expression(
+        &mut self,
+        precedence: Precedence,
+    ) -> Result<ast::Expression, Diagnostic> {
+        let Some(prefix) = self.prefix_parse_fns.get(&self.current_token.kind).cloned() else {
+            return Err(self.expected_expression_error());
+        };
+
+        let mut left = prefix.parse(self)?;
+
+        while !self.peek_token_is(TokenKind::Semicolon) && precedence < self.peek_precedence() {
+            let Some(infix) = self.infix_parse_fns.get(&self.peek_token.kind).cloned() else {
+                return Ok(left);
+            };
+
+            self.next_token();
+            left = infix.parse(self, left)?;
+        }
+
+        Ok(left)
+    }
+
+    /// Parses statements up to the matching closing brace.  Expects the current token
+    /// to be the opening brace and leaves the closing brace as the current token
+    fn parse_block_statement(&mut self) -> Result<ast::BlockStatement, Diagnostic> {
+        let token = self.current_token.clone();
+        let mut statements = Vec::new();
+
+        self.next_token();
+
+        while !self.current_token_is(TokenKind::RBrace) {
+            if self.current_token_is(TokenKind::Eof) {
+                return Err(Diagnostic::error(
+                    diagnostic::UNCLOSED_DELIMITER,
+                    "expected }, got EOF instead",
+                    self.current_token.span,
+                )
+                .with_label(token.span, "unclosed delimiter"));
             }
-            _ => {
-                return Err(anyhow::anyhow!("Error could not parse statement!"));
+
+            match self.parse_statement() {
+                Ok(statement) => statements.push(statement),
+                Err(err) if err.code == diagnostic::TOO_DEEPLY_NESTED => return Err(err),
+                Err(err) => {
+                    self.report(err);
+                    self.synchronize();
+                    if self.current_token_is(TokenKind::RBrace) {
+                        break;
+                    }
+                }
             }
+
+            self.next_token();
         }
+
+        Ok(ast::BlockStatement { token, statements })
     }
 
-    fn parse_let_statement(&mut self) -> Result<ast::Statement, anyhow::Error> {
-        if !self.expect_peek(token::IDENT)? {
-            self.errors.push("expected identifier".to_owned());
-            return Err(anyhow::anyhow!("Expected Identifier"));
+    fn parse_function_parameters(&mut self) -> Result<Vec<ast::Identifier>, Diagnostic> {
+        let mut params = Vec::new();
+
+        if self.peek_token_is(TokenKind::RParen) {
+            self.next_token();
+            return Ok(params);
         }
 
-        let ident = ast::Identifier::new(self.current_token.clone(), &self.current_token.literal);
+        loop {
+            self.expect_peek(TokenKind::Ident)?;
+            params.push(ast::Identifier::new(
+                self.current_token.clone(),
+                self.current_token.literal(),
+            ));
+
+            if !self.peek_token_is(TokenKind::Comma) {
+                break;
+            }
+            self.next_token();
+        }
+
+        self.expect_peek(TokenKind::RParen)?;
+
+        Ok(params)
+    }
+
+    /// Parses comma-separated expressions up to the closing `end` token, such as the
+    /// arguments of a call or the elements of an array
+    fn parse_expression_list(
+        &mut self,
+        end: TokenKind,
+    ) -> Result<Vec<ast::Expression>, Diagnostic> {
+        let mut expressions = Vec::new();
 
-        if !self.expect_peek(token::ASSIGN)? {
-            self.errors.push("expected assign".to_owned());
-            return Err(anyhow::anyhow!("Expected Assign"));
+        if self.peek_token_is(end) {
+            self.next_token();
+            return Ok(expressions);
         }
 
-        while !self.current_token_is(token::SEMICOLON) {
-            self.next_token()?;
+        self.next_token();
+        expressions.push(self.parse_expression(Precedence::Lowest)?);
+
+        while self.peek_token_is(TokenKind::Comma) {
+            self.next_token();
+            self.next_token();
+            expressions.push(self.parse_expression(Precedence::Lowest)?);
         }
 
-        // TODO parse expression
-        let expression = ast::Expression::Integer(token::Token::new(token::INT, "42"));
+        self.expect_peek(end)?;
 
-        return Ok(ast::Statement::Let(ident, expression));
+        Ok(expressions)
     }
 
-    fn parse_return_statement(&mut self) -> Result<ast::Statement, anyhow::Error> {
-        // TODO parse expression
-        let expression = ast::Expression::Integer(token::Token::new(token::INT, "42"));
+    /// Advances if the next token has the given type, otherwise reports it as
+    /// unexpected without consuming it
+    fn expect_peek(&mut self, kind: TokenKind) -> Result<(), Diagnostic> {
+        if self.peek_token_is(kind) {
+            self.next_token();
+            return Ok(());
+        }
 
-        while !self.current_token_is(token::SEMICOLON) {
-            self.next_token()?;
+        Err(Diagnostic::error(
+            diagnostic::UNEXPECTED_TOKEN,
+            format!(
+                "expected next token to be {}, got {} instead",
+                kind, self.peek_token.kind
+            ),
+            self.peek_token.span,
+        ))
+    }
+
+    /// Records a diagnostic, unless it was already reported or is about a token the
+    /// lexer rejected.  The lexer's error says what is wrong with such a token, while
+    /// the parser would only add that it is not what was expected there
+    fn report(&mut self, err: Diagnostic) {
+        let duplicate = self.rejected.contains(&err.span) || self.errors.contains(&err);
+
+        if !duplicate {
+            self.errors.push(err);
         }
+    }
+
+    /// Skips ahead to a likely statement boundary after an error, so the next
+    /// statement can be parsed on its own.  Stops on a `;` or `}`, or just before a
+    /// `let` or `return`
+    fn synchronize(&mut self) {
+        while !self.current_token_is(TokenKind::Eof) {
+            if self.current_token_is(TokenKind::Semicolon)
+                || self.current_token_is(TokenKind::RBrace)
+                || self.peek_token_is(TokenKind::Let)
+                || self.peek_token_is(TokenKind::Return)
+            {
+                return;
+            }
 
-        return Ok(ast::Statement::Return(expression));
+            self.next_token();
+        }
     }
 
-    fn expect_peek(&mut self, token_type: &str) -> Result<bool, anyhow::Error> {
-        if self.peek_token_is(token_type) {
-            self.next_token()?;
-            return Ok(true);
-        } else {
-            self.errors.push(format!(
-                "expected next token to be {}, got {} instead",
-                token_type, self.peek_token.token_type
-            ));
-            return Ok(false);
+    fn expected_expression_error(&self) -> Diagnostic {
+        let token = &self.current_token;
+
+        match token.kind {
+            TokenKind::Illegal => Diagnostic::error(
+                diagnostic::ILLEGAL_CHARACTER,
+                format!("illegal character {}", token.literal()),
+                token.span,
+            ),
+            TokenKind::Eof => Diagnostic::error(
+                diagnostic::EXPECTED_EXPRESSION,
+                "expected an expression, got EOF instead",
+                token.span,
+            ),
+            _ => Diagnostic::error(
+                diagnostic::EXPECTED_EXPRESSION,
+                format!("expected an expression, got {} instead", token.literal()),
+                token.span,
+            )
+            .with_note(format!("{} cannot start an expression", token.kind)),
         }
     }
 
-    fn current_token_is(&self, token_type: &str) -> bool {
-        return self.current_token.token_type == token_type;
+    fn current_token_is(&self, kind: TokenKind) -> bool {
+        self.current_token.kind == kind
     }
 
-    fn peek_token_is(&self, token_type: &str) -> bool {
-        return self.peek_token.token_type == token_type;
+    fn peek_token_is(&self, kind: TokenKind) -> bool {
+        self.peek_token.kind == kind
+    }
+
+    fn current_precedence(&self) -> Precedence {
+        Precedence::of(self.current_token.kind)
+    }
+
+    fn peek_precedence(&self) -> Precedence {
+        Precedence::of(self.peek_token.kind)
     }
 }
 
@@ -141,7 +683,7 @@ mod tests {
     use super::*;
 
     fn verify_no_parser_errors(parser: Parser) -> bool {
-        if parser.errors.len() == 0 {
+        if parser.errors.is_empty() {
             return true;
         }
 
@@ -149,26 +691,42 @@ mod tests {
             println!("{}", err);
         }
 
-        return false;
+        false
     }
 
     fn create_expected_let_statement(id_name: &str, exp_val: &str) -> ast::Statement {
-        let expected_token = token::Token::new(token::IDENT, id_name);
+        let expected_token = token::Token::new(TokenKind::Ident, id_name);
         let expected_ident = ast::Identifier::new(expected_token, id_name);
-        let expected_expression = ast::Expression::Integer(token::Token::new(token::INT, exp_val));
-        let expected_statement = ast::Statement::Let(expected_ident, expected_expression);
-        return expected_statement;
+        let expected_expression =
+            ast::Expression::Integer(token::Token::new(TokenKind::Int, exp_val));
+        ast::Statement::Let(
+            token::Token::new(TokenKind::Let, "let"),
+            expected_ident,
+            expected_expression,
+        )
     }
 
     fn create_expected_expression_statement(
-        token_type: &str,
+        kind: TokenKind,
         literal: &str,
         name: &str,
     ) -> ast::Statement {
-        let expected_token = token::Token::new(token_type, literal);
+        let expected_token = token::Token::new(kind, literal);
         let expected_expression =
-            ast::Expression::Identifier(token::Token::new(token::IDENT, name));
-        return ast::Statement::Expression(expected_token, expected_expression);
+            ast::Expression::Identifier(token::Token::new(TokenKind::Ident, name));
+        ast::Statement::Expression(expected_token, expected_expression)
+    }
+
+    fn parse_let_value(input: &str) -> ast::Expression {
+        let lexer = lexer::Lexer::new(input);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+
+        assert_eq!(1, program.statements.len());
+        match program.statements.into_iter().next().unwrap() {
+            ast::Statement::Let(_, _, expression) => expression,
+            statement => panic!("expected let statement, got {:?}", statement),
+        }
     }
 
     #[test]
@@ -180,39 +738,122 @@ mod tests {
         "#;
 
         let lexer = lexer::Lexer::new(input);
-        let mut parser = Parser::new(lexer).unwrap();
-        let program = parser.parse_program().unwrap();
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
 
         assert_eq!(3, program.statements.len());
         assert_eq!(
-            program.statements.get(0).unwrap(),
-            &create_expected_let_statement("x", "42")
+            program.statements.first().unwrap(),
+            &create_expected_let_statement("x", "5")
         );
         assert_eq!(
             program.statements.get(1).unwrap(),
-            &create_expected_let_statement("y", "42")
+            &create_expected_let_statement("y", "10")
         );
         assert_eq!(
             program.statements.get(2).unwrap(),
-            &create_expected_let_statement("foobar", "42")
+            &create_expected_let_statement("foobar", "838383")
         );
-        assert_eq!(true, verify_no_parser_errors(parser));
-        println!("{}", program.to_string());
+        assert!(verify_no_parser_errors(parser));
+        println!("{}", program);
     }
 
     #[test]
     fn test_let_statements_error() {
         let input = r#"
         let x = 5;
-        let y = 10;
+        let y 10;
         let 838383;
+        let = 1;
+        let z = 15;
         "#;
 
         let lexer = lexer::Lexer::new(input);
-        let mut parser = Parser::new(lexer).unwrap();
-        let _ = parser.parse_program().unwrap_err();
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
 
-        assert_eq!(false, verify_no_parser_errors(parser));
+        let rendered: Vec<String> = program.statements.iter().map(ToString::to_string).collect();
+        assert_eq!(vec!["let x = 5;", "let z = 15;"], rendered);
+
+        let messages: Vec<String> = parser.errors.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            vec![
+                "error[E0001]: 3:15: expected next token to be =, got INT instead",
+                "error[E0001]: 4:13: expected next token to be IDENT, got INT instead",
+                "error[E0001]: 5:13: expected next token to be IDENT, got = instead",
+            ],
+            messages
+        );
+
+        let error = &parser.errors[1];
+        assert_eq!(diagnostic::UNEXPECTED_TOKEN, error.code);
+        assert_eq!(
+            "error[E0001]: 4:13: expected next token to be IDENT, got INT instead",
+            error.to_string()
+        );
+        assert_eq!(
+            "error[E0001]: expected next token to be IDENT, got INT instead\n \
+             --> <test>:4:13\n  \
+             |\n\
+             4 |         let 838383;\n  \
+             |         --- ^^^^^^\n  \
+             |         in this let statement\n",
+            error.render("<test>", input)
+        );
+        assert!(!verify_no_parser_errors(parser));
+    }
+
+    #[test]
+    fn test_recovery_inside_blocks() {
+        let input = r#"
+        let f = fn(x) {
+            let = 1;
+            x
+        };
+        if (f(1) > ) { 2 }
+        let y = 2;
+        "#;
+
+        let lexer = lexer::Lexer::new(input);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+
+        let rendered: Vec<String> = program.statements.iter().map(ToString::to_string).collect();
+        assert_eq!(vec!["let f = fn(x) { x };", "let y = 2;"], rendered);
+
+        let messages: Vec<String> = parser.errors.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            vec![
+                "error[E0001]: 3:17: expected next token to be IDENT, got = instead",
+                "error[E0002]: 6:20: expected an expression, got ) instead",
+            ],
+            messages
+        );
+    }
+
+    #[test]
+    fn test_lexer_errors_are_reported_once() {
+        let lexer = lexer::Lexer::new("let a = 99999999999999999999; let b = 2;");
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+
+        assert_eq!(1, program.statements.len());
+        assert_eq!(1, parser.errors.len());
+        assert_eq!(diagnostic::INVALID_NUMBER, parser.errors[0].code);
+    }
+
+    #[test]
+    fn test_errors_at_the_same_place_are_all_reported() {
+        let lexer = lexer::Lexer::new("let f = fn(x) { if (x) { x");
+        let mut parser = Parser::new(lexer);
+        let _ = parser.parse_program();
+
+        let labels: Vec<usize> = parser
+            .errors
+            .iter()
+            .map(|err| err.labels[0].span.start)
+            .collect();
+        assert_eq!(vec![23, 14], labels);
     }
 
     #[test]
@@ -224,15 +865,18 @@ mod tests {
         "#;
 
         let lexer = lexer::Lexer::new(input);
-        let mut parser = Parser::new(lexer).unwrap();
-        let program = parser.parse_program().unwrap();
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
 
         assert_eq!(3, program.statements.len());
         assert!(verify_no_parser_errors(parser));
-        assert!(matches!(
-            program.statements.get(0).unwrap(),
-            ast::Statement::Return { .. }
-        ));
+        assert_eq!(
+            program.statements.first().unwrap(),
+            &ast::Statement::Return(
+                token::Token::new(TokenKind::Return, "return"),
+                ast::Expression::Integer(token::Token::new(TokenKind::Int, "5"))
+            )
+        );
         assert!(matches!(
             program.statements.get(1).unwrap(),
             ast::Statement::Return { .. }
@@ -242,6 +886,443 @@ mod tests {
             ast::Statement::Return { .. }
         ));
 
-        println!("{}", program.to_string());
+        println!("{}", program);
+    }
+
+    #[test]
+    fn test_prefix_expressions() {
+        let expression = parse_let_value("let x = -15;");
+
+        assert_eq!(
+            expression,
+            ast::Expression::Prefix {
+                token: token::Token::new(TokenKind::Minus, "-"),
+                right: Box::new(ast::Expression::Integer(token::Token::new(
+                    TokenKind::Int,
+                    "15"
+                ))),
+            }
+        );
+        assert_eq!("(!foo)", parse_let_value("let x = !foo;").to_string());
+    }
+
+    #[test]
+    fn test_operator_precedence() {
+        let cases = [
+            ("-a * b", "((-a) * b)"),
+            ("!-a", "(!(-a))"),
+            ("a + b + c", "((a + b) + c)"),
+            ("a + b - c", "((a + b) - c)"),
+            ("a * b * c", "((a * b) * c)"),
+            ("a * b / c", "((a * b) / c)"),
+            ("a + b / c", "(a + (b / c))"),
+            ("a + b * c + d / e - f", "(((a + (b * c)) + (d / e)) - f)"),
+            ("5 > 4 == 3 < 4", "((5 > 4) == (3 < 4))"),
+            ("5 < 4 != 3 > 4", "((5 < 4) != (3 > 4))"),
+            (
+                "3 + 4 * 5 == 3 * 1 + 4 * 5",
+                "((3 + (4 * 5)) == ((3 * 1) + (4 * 5)))",
+            ),
+            ("1 + (2 + 3) + 4", "((1 + (2 + 3)) + 4)"),
+            ("(5 + 5) * 2", "((5 + 5) * 2)"),
+            ("-(5 + 5)", "(-(5 + 5))"),
+            ("a + add(b * c) + d", "((a + add((b * c))) + d)"),
+            (
+                "add(a, b, 1, 2 * 3, 4 + 5, add(6, 7 * 8))",
+                "add(a, b, 1, (2 * 3), (4 + 5), add(6, (7 * 8)))",
+            ),
+            (
+                "add(a + b + c * d / f + g)",
+                "add((((a + b) + ((c * d) / f)) + g))",
+            ),
+            (
+                "a * [1, 2, 3, 4][b * c] * d",
+                "((a * ([1, 2, 3, 4][(b * c)])) * d)",
+            ),
+            (
+                "add(a * b[2], b[1], 2 * [1, 2][1])",
+                "add((a * (b[2])), (b[1]), (2 * ([1, 2][1])))",
+            ),
+        ];
+
+        for (input, expected) in cases {
+            let expression = parse_let_value(&format!("let x = {};", input));
+            assert_eq!(expected, expression.to_string(), "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_call_expression() {
+        let expression = parse_let_value("let x = add();");
+
+        assert_eq!(
+            expression,
+            ast::Expression::Call {
+                token: token::Token::new(TokenKind::LParen, "("),
+                function: Box::new(ast::Expression::Identifier(token::Token::new(
+                    TokenKind::Ident,
+                    "add"
+                ))),
+                args: Vec::new(),
+            }
+        );
+    }
+
+    #[test]
+    fn test_expression_statements() {
+        let input = r#"
+        foobar;
+        5 + 5;
+        add(1, 2)
+        "#;
+
+        let lexer = lexer::Lexer::new(input);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+
+        assert_eq!(3, program.statements.len());
+        assert!(verify_no_parser_errors(parser));
+        assert_eq!(
+            program.statements.first().unwrap(),
+            &create_expected_expression_statement(TokenKind::Ident, "foobar", "foobar")
+        );
+
+        let rendered: Vec<String> = program
+            .statements
+            .iter()
+            .map(|statement| match statement {
+                ast::Statement::Expression(_, expression) => expression.to_string(),
+                statement => panic!("expected expression statement, got {:?}", statement),
+            })
+            .collect();
+        assert_eq!(vec!["foobar", "(5 + 5)", "add(1, 2)"], rendered);
+    }
+
+    #[test]
+    fn test_boolean_expressions() {
+        let cases = [
+            ("true", "true"),
+            ("3 > 5 == false", "((3 > 5) == false)"),
+            ("!(true == true)", "(!(true == true))"),
+        ];
+
+        for (input, expected) in cases {
+            let expression = parse_let_value(&format!("let x = {};", input));
+            assert_eq!(expected, expression.to_string(), "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_float_literal() {
+        let expression = parse_let_value("let x = 1_000.5e-2;");
+
+        match &expression {
+            ast::Expression::Float(token) => assert_eq!("1000.5e-2", token.literal()),
+            other => panic!("expected a float literal, got {:?}", other),
+        }
+        assert_eq!(
+            "((-1.5) * 2)",
+            parse_let_value("let x = -1.5 * 2;").to_string()
+        );
+    }
+
+    #[test]
+    fn test_string_literal() {
+        let expression = parse_let_value(r#"let x = "hello\tworld";"#);
+
+        match &expression {
+            ast::Expression::StringLiteral(token) => assert_eq!("hello\tworld", token.literal()),
+            other => panic!("expected a string literal, got {:?}", other),
+        }
+        assert_eq!(r#""hello\tworld""#, expression.to_string());
+    }
+
+    #[test]
+    fn test_array_literal() {
+        let cases = [
+            ("[]", "[]"),
+            ("[1, 2 * 2, 3 + 3]", "[1, (2 * 2), (3 + 3)]"),
+            ("[[1], \"a\"]", "[[1], \"a\"]"),
+        ];
+
+        for (input, expected) in cases {
+            let expression = parse_let_value(&format!("let x = {};", input));
+            assert!(
+                matches!(expression, ast::Expression::ArrayLiteral { .. }),
+                "input: {}",
+                input
+            );
+            assert_eq!(expected, expression.to_string(), "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_hash_literal() {
+        let cases = [
+            ("{}", "{}"),
+            (
+                "{\"one\": 1, \"two\": 2, \"three\": 3}",
+                "{\"one\": 1, \"two\": 2, \"three\": 3}",
+            ),
+            ("{1: true, false: 0 + 1}", "{1: true, false: (0 + 1)}"),
+            ("{\"a\": {\"b\": [1]}}", "{\"a\": {\"b\": [1]}}"),
+        ];
+
+        for (input, expected) in cases {
+            let expression = parse_let_value(&format!("let x = {};", input));
+            assert!(
+                matches!(expression, ast::Expression::HashLiteral { .. }),
+                "input: {}",
+                input
+            );
+            assert_eq!(expected, expression.to_string(), "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_hash_literal_errors() {
+        let cases = [
+            (
+                "{1 2}",
+                "error[E0001]: 1:4: expected next token to be :, got INT instead",
+            ),
+            (
+                "{1: 2,}",
+                "error[E0002]: 1:7: expected an expression, got } instead",
+            ),
+            (
+                "{1: 2",
+                "error[E0001]: 1:6: expected next token to be }, got EOF instead",
+            ),
+        ];
+
+        for (input, expected) in cases {
+            let mut parser = Parser::new(lexer::Lexer::new(input));
+            let _ = parser.parse_program();
+
+            let messages: Vec<String> = parser.errors.iter().map(ToString::to_string).collect();
+            assert_eq!(vec![expected], messages, "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_index_expression() {
+        let expression = parse_let_value("let x = myArray[1 + 1];");
+
+        match &expression {
+            ast::Expression::Index { left, index, .. } => {
+                assert_eq!("myArray", left.to_string());
+                assert_eq!("(1 + 1)", index.to_string());
+            }
+            other => panic!("expected an index expression, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_if_expression() {
+        let expression = parse_let_value("let x = if (x < y) { x };");
+
+        let ast::Expression::If {
+            condition,
+            consequence,
+            alternative,
+            ..
+        } = expression
+        else {
+            panic!("expected if expression, got {:?}", expression);
+        };
+        assert_eq!("(x < y)", condition.to_string());
+        assert_eq!(
+            consequence.statements,
+            vec![create_expected_expression_statement(
+                TokenKind::Ident,
+                "x",
+                "x"
+            )]
+        );
+        assert_eq!(None, alternative);
+    }
+
+    #[test]
+    fn test_if_else_expression() {
+        let expression = parse_let_value("let x = if (x < y) { x } else { let z = y; z };");
+
+        assert_eq!(
+            "if (x < y) { x } else { let z = y; z }",
+            expression.to_string()
+        );
+    }
+
+    #[test]
+    fn test_function_literal() {
+        let cases = [
+            ("fn() {}", "fn() {}"),
+            ("fn(x) { x }", "fn(x) { x }"),
+            ("fn(x, y) { x + y; }", "fn(x, y) { (x + y) }"),
+            (
+                "fn(x, y) { return x; }(1, 2)",
+                "fn(x, y) { return x; }(1, 2)",
+            ),
+        ];
+
+        for (input, expected) in cases {
+            let expression = parse_let_value(&format!("let f = {};", input));
+            assert_eq!(expected, expression.to_string(), "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_lexer_source_code() {
+        let input = r#"
+        let five = 5;
+        let ten = 10;
+
+        let add = fn(x, y) {
+            x + y;
+        };
+
+        let result = add(five, ten);
+
+        if (5 < 10)  {
+            return true;
+        } else {
+            return false;
+        }
+        "#;
+
+        let lexer = lexer::Lexer::new(input);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+
+        assert!(verify_no_parser_errors(parser));
+        let rendered: Vec<String> = program.statements.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            vec![
+                "let five = 5;",
+                "let ten = 10;",
+                "let add = fn(x, y) { (x + y) };",
+                "let result = add(five, ten);",
+                "if (5 < 10) { return true; } else { return false; }",
+            ],
+            rendered
+        );
+    }
+
+    #[test]
+    fn test_expression_spans() {
+        let input = "let total = add(1, 2) * -x;";
+        let expression = parse_let_value(input);
+        let span = expression.span();
+
+        assert_eq!("add(1, 2) * -x", &input[span.start..span.end]);
+        assert_eq!((1, 13), (span.line, span.column));
+        assert_eq!("*", expression.token().literal());
+        assert_eq!(23, expression.token().span.column);
+
+        for (input, expected) in [
+            ("let first = [1, 2][0];", "[1, 2][0]"),
+            ("let n = f(1, 2);", "f(1, 2)"),
+            ("let n = len([1], 2);", "len([1], 2)"),
+            ("let n = f();", "f()"),
+            (
+                "let n = if (x) { 1 } else { 2 };",
+                "if (x) { 1 } else { 2 }",
+            ),
+            ("let n = if (x) { 1 };", "if (x) { 1 }"),
+            ("let f = fn(x) { x };", "fn(x) { x }"),
+        ] {
+            let span = parse_let_value(input).span();
+            assert_eq!(expected, &input[span.start..span.end], "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_statement_spans() {
+        let input = "let x = 5;\nreturn x * 2;\nx + 1";
+        let lexer = lexer::Lexer::new(input);
+        let mut parser = Parser::new(lexer);
+        let program = parser.parse_program();
+        assert!(verify_no_parser_errors(parser));
+
+        let spans: Vec<&str> = program
+            .statements
+            .iter()
+            .map(|statement| {
+                let span = statement.span();
+                &input[span.start..span.end]
+            })
+            .collect();
+        assert_eq!(vec!["let x = 5", "return x * 2", "x + 1"], spans);
+    }
+
+    #[test]
+    fn test_unterminated_block_error() {
+        let lexer = lexer::Lexer::new("fn(x) { x");
+        let mut parser = Parser::new(lexer);
+        let _ = parser.parse_program();
+
+        let messages: Vec<String> = parser.errors.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            vec!["error[E0003]: 1:10: expected }, got EOF instead"],
+            messages
+        );
+        assert_eq!(6, parser.errors[0].labels[0].span.start);
+    }
+
+    #[test]
+    fn test_missing_prefix_error() {
+        let lexer = lexer::Lexer::new("let x = *5;");
+        let mut parser = Parser::new(lexer);
+        let _ = parser.parse_program();
+
+        let messages: Vec<String> = parser.errors.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            vec!["error[E0002]: 1:9: expected an expression, got * instead"],
+            messages
+        );
+    }
+
+    #[test]
+    fn test_illegal_character_error() {
+        let lexer = lexer::Lexer::new("let x = 5 + @;");
+        let mut parser = Parser::new(lexer);
+        let _ = parser.parse_program();
+
+        let messages: Vec<String> = parser.errors.iter().map(ToString::to_string).collect();
+        assert_eq!(vec!["error[E0005]: 1:13: illegal character @"], messages);
+    }
+
+    #[test]
+    fn test_unterminated_string_error() {
+        let lexer = lexer::Lexer::new("let x = 1;\nlet y = \"abc;");
+        let mut parser = Parser::new(lexer);
+        let _ = parser.parse_program();
+
+        let messages: Vec<String> = parser.errors.iter().map(ToString::to_string).collect();
+        assert_eq!(vec!["error[E0006]: 2:9: unterminated string"], messages);
+    }
+
+    #[test]
+    fn test_nesting_limit() {
+        let nest = |open: &str, close: &str, depth: usize| {
+            format!("{}1{}", open.repeat(depth), close.repeat(depth))
+        };
+
+        // The outermost expression is one level, so the limit allows one less opening
+        for (open, close) in [("(", ")"), ("-", ""), ("[", "]"), ("fn() { ", " }")] {
+            let input = nest(open, close, MAX_NESTING_DEPTH - 1);
+            let lexer = lexer::Lexer::new(&input);
+            let mut parser = Parser::new(lexer);
+            let _ = parser.parse_program();
+            assert!(verify_no_parser_errors(parser), "nesting {}", open);
+
+            let input = nest(open, close, 100_000);
+            let lexer = lexer::Lexer::new(&input);
+            let mut parser = Parser::new(lexer);
+            let _ = parser.parse_program();
+
+            assert_eq!(1, parser.errors.len(), "nesting {}", open);
+            assert_eq!(diagnostic::TOO_DEEPLY_NESTED, parser.errors[0].code);
+        }
     }
 }
@@ -1,31 +1,390 @@
-use crate::lexer;
-use crate::token;
-use std::io::Write;
+use monkey::diagnostic::{self, Diagnostic};
+use monkey::environment::Environment;
+use monkey::evaluator::{self, IntegerMode};
+use monkey::interrupt::InterruptHandle;
+use monkey::lexer;
+use monkey::object::Object;
+use monkey::parser;
+use monkey::token::TokenKind;
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::cell::RefCell;
+use std::path::PathBuf;
+use std::rc::Rc;
+#[cfg(unix)]
+use std::sync::OnceLock;
+
+/// Name the REPL reports as the source of diagnostics
+const SOURCE_NAME: &str = "<repl>";
+
+const PROMPT: &str = ">> ";
+/// Shown while an entry has unclosed parentheses, braces or brackets
+const CONTINUATION_PROMPT: &str = ".. ";
+
+/// History file, relative to the home directory
+const HISTORY_FILE: &str = ".monkey_history";
+
+const HELP: &str = "\
+:eval        evaluate each line and print its value (default)
+:ast         print the parsed program for each line
+:tokens      print the tokens of each line
+:env         list the bindings made so far
+:load FILE   evaluate FILE, keeping its bindings
+:reset       forget every binding
+:help        print this help
+:quit        leave the REPL, as does Ctrl-D
+
+Ctrl-C discards the current entry, or stops an evaluation that is running";
+
+/// One evaluated entry, kept so that an error raised later inside a function it
+/// defined still renders against its code
+#[derive(Debug)]
+struct Entry {
+    /// `<repl>`, or the path of a file given to `:load`
+    name: String,
+    /// Where the entry's spans start.  Each entry has its own range of offsets, so a
+    /// span shows which entry it points into
+    offset: usize,
+    text: String,
+}
+
+/// Every entry evaluated since the session started or was last reset
+#[derive(Debug, Default)]
+struct Session {
+    entries: Vec<Entry>,
+}
+
+impl Session {
+    /// Records `text` as the next entry and returns the offset its spans start at
+    fn push(&mut self, name: &str, text: &str) -> usize {
+        // Leave a gap so the end of input span of one entry is not the start of
+        // the next
+        let offset = self
+            .entries
+            .last()
+            .map_or(0, |entry| entry.offset + entry.text.len() + 1);
+
+        self.entries.push(Entry {
+            name: name.to_owned(),
+            offset,
+            text: text.to_owned(),
+        });
+        offset
+    }
+
+    /// Renders `err` against the entry its span points into, with the span made
+    /// relative to that entry
+    fn render(&self, err: &Diagnostic) -> String {
+        let index = self
+            .entries
+            .partition_point(|entry| entry.offset <= err.span.start)
+            .saturating_sub(1);
+        let Some(entry) = self.entries.get(index) else {
+            return format!("{}\n", err);
+        };
+
+        let mut err = err.clone();
+        let spans = std::iter::once(&mut err.span)
+            .chain(err.labels.iter_mut().map(|label| &mut label.span));
+        for span in spans {
+            span.start = span.start.saturating_sub(entry.offset);
+            span.end = span.end.saturating_sub(entry.offset);
+        }
+        err.render(&entry.name, &entry.text)
+    }
+}
+
+/// What the REPL prints for each line of input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Tokens,
+    Ast,
+    Eval,
+}
+
+pub fn start(integer_mode: IntegerMode) -> Result<(), anyhow::Error> {
+    let mut mode = Mode::Eval;
+    let interrupt = catch_interrupts();
+    let new_env =
+        || Environment::with_integer_mode(integer_mode).with_interrupt_handle(interrupt.clone());
+    // Bindings live for the whole session, so a later line can use an earlier `let`
+    let mut env = Rc::new(RefCell::new(new_env()));
+    let mut session = Session::default();
+    let mut editor = DefaultEditor::new()?;
+    let history = history_path();
+
+    if let Some(history) = &history {
+        // A missing history file just means this is the first session
+        let _ = editor.load_history(history);
+    }
 
-pub fn start() -> Result<(), anyhow::Error> {
     loop {
-        // Grab user input
-        print!(">> ");
-        std::io::stdout().flush()?;
-        let mut input = String::new();
-        std::io::stdin().read_line(&mut input)?;
+        let input = match read_entry(&mut editor, integer_mode) {
+            Ok(input) => input,
+            // Ctrl-C throws away whatever has been typed so far
+            Err(ReadlineError::Interrupted) => continue,
+            // Ctrl-D
+            Err(ReadlineError::Eof) => return Ok(()),
+            Err(err) => return Err(err.into()),
+        };
 
-        let mut lexer = lexer::Lexer::new(&input);
+        if input.trim().is_empty() {
+            continue;
+        }
 
-        loop {
-            let token = lexer.next_token()?;
+        editor.add_history_entry(input.as_str())?;
+        if let Some(history) = &history {
+            if let Err(err) = editor.save_history(history) {
+                eprintln!("could not save history to {}: {}", history.display(), err);
+            }
+        }
 
-            match token.token_type.as_str() {
-                token::ILLEGAL => {
-                    println!("Illegal! {:?}", token);
-                }
-                token::EOF => {
-                    break;
-                }
-                _ => {
-                    println!("Token: {:?}", token);
+        if let Some(command) = input.trim().strip_prefix(':') {
+            let (command, arg) = command
+                .split_once(char::is_whitespace)
+                .map_or((command, ""), |(command, arg)| (command, arg.trim()));
+
+            match (command, arg) {
+                ("tokens", "") => mode = Mode::Tokens,
+                ("ast", "") => mode = Mode::Ast,
+                ("eval", "") => mode = Mode::Eval,
+                ("env", "") => print_env(&env),
+                ("reset", "") => {
+                    env = Rc::new(RefCell::new(new_env()));
+                    session = Session::default();
                 }
+                ("load", "") => eprintln!("usage: :load FILE"),
+                ("load", path) => load(path, &mut session, &env),
+                ("help", "") => println!("{}", HELP),
+                ("quit", "") => return Ok(()),
+                _ => eprintln!("unknown command {}, try :help", input.trim()),
             }
+            continue;
+        }
+
+        match mode {
+            Mode::Tokens => print_tokens(&input, integer_mode),
+            Mode::Ast => print_ast(&input, integer_mode),
+            Mode::Eval => print_eval(SOURCE_NAME, &input, &mut session, &env),
+        }
+    }
+}
+
+/// Reads one entry, prompting for more lines while it is incomplete
+fn read_entry(editor: &mut DefaultEditor, integer_mode: IntegerMode) -> rustyline::Result<String> {
+    let mut input = editor.readline(PROMPT)?;
+    while is_incomplete(&input, integer_mode) {
+        let line = editor.readline(CONTINUATION_PROMPT)?;
+        input.push('\n');
+        input.push_str(&line);
+    }
+    Ok(input)
+}
+
+/// Makes Ctrl-C interrupt the running evaluation instead of killing the process.
+/// While a line is being read the editor puts the terminal in raw mode and sees
+/// Ctrl-C as a key press, so the signal only arrives during evaluation.  Returns the
+/// handle Ctrl-C triggers, which every environment of the session must share
+#[cfg(unix)]
+fn catch_interrupts() -> InterruptHandle {
+    static INTERRUPT: OnceLock<InterruptHandle> = OnceLock::new();
+
+    extern "C" fn on_sigint(_: libc::c_int) {
+        if let Some(interrupt) = INTERRUPT.get() {
+            interrupt.interrupt();
         }
     }
+
+    let interrupt = INTERRUPT.get_or_init(InterruptHandle::default).clone();
+
+    let handler: extern "C" fn(libc::c_int) = on_sigint;
+    // SAFETY: the handler only stores to an atomic, which is async-signal-safe
+    unsafe {
+        libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+    }
+
+    interrupt
+}
+
+#[cfg(not(unix))]
+fn catch_interrupts() -> InterruptHandle {
+    InterruptHandle::default()
+}
+
+fn history_path() -> Option<PathBuf> {
+    std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE))
+}
+
+/// Whether `input` opens more parentheses, braces or brackets than it closes,
+/// meaning the user is still typing, e.g. the body of a function.  Literals are
+/// read as the session reads them, so that one only too big without `--bigint`
+/// does not end the entry early
+fn is_incomplete(input: &str, integer_mode: IntegerMode) -> bool {
+    let mut lexer = lexer::Lexer::new(input).with_integer_mode(integer_mode);
+    let mut depth: usize = 0;
+
+    loop {
+        match lexer.next_token().map(|token| token.kind) {
+            Ok(TokenKind::LParen | TokenKind::LBrace | TokenKind::LBracket) => depth += 1,
+            Ok(TokenKind::RParen | TokenKind::RBrace | TokenKind::RBracket) => {
+                depth = depth.saturating_sub(1);
+            }
+            Ok(TokenKind::Eof) => return depth > 0,
+            // A lexer error is better reported than waited on
+            Err(_) => return false,
+            Ok(_) => {}
+        }
+    }
+}
+
+fn print_tokens(input: &str, integer_mode: IntegerMode) {
+    let mut lexer = lexer::Lexer::new(input).with_integer_mode(integer_mode);
+
+    loop {
+        let token = match lexer.next_token() {
+            Ok(token) => token,
+            Err(err) => {
+                eprint!("{}", err.render(SOURCE_NAME, input));
+                break;
+            }
+        };
+
+        match token.kind {
+            TokenKind::Illegal => {
+                let err = Diagnostic::error(
+                    diagnostic::ILLEGAL_CHARACTER,
+                    format!("illegal character {}", token.literal()),
+                    token.span,
+                );
+                eprint!("{}", err.render(SOURCE_NAME, input));
+            }
+            TokenKind::Eof => {
+                break;
+            }
+            _ => {
+                println!(
+                    "{:<12} {:<12} {}",
+                    format!("{:?}", token.kind),
+                    token.literal(),
+                    token.span
+                );
+            }
+        }
+    }
+}
+
+fn print_ast(input: &str, integer_mode: IntegerMode) {
+    let lexer = lexer::Lexer::new(input).with_integer_mode(integer_mode);
+    let mut parser = parser::Parser::new(lexer);
+    let program = parser.parse_program();
+
+    for err in &parser.errors {
+        eprint!("{}", err.render(SOURCE_NAME, input));
+    }
+    print!("{}", program);
+}
+
+/// Records `input` as an entry of the session named `name`, evaluates it in the
+/// session environment and prints its value
+fn print_eval(name: &str, input: &str, session: &mut Session, env: &Rc<RefCell<Environment>>) {
+    let offset = session.push(name, input);
+
+    let integer_mode = env.borrow().integer_mode();
+    let lexer = lexer::Lexer::new_at(input, offset).with_integer_mode(integer_mode);
+    let mut parser = parser::Parser::new(lexer);
+    let program = parser.parse_program();
+
+    if !parser.errors.is_empty() {
+        for err in &parser.errors {
+            eprint!("{}", session.render(err));
+        }
+        return;
+    }
+
+    let result = evaluator::eval_program(&program, env);
+
+    if let Some(err) = result.to_diagnostic() {
+        eprint!("{}", session.render(&err));
+    } else if result != Object::Null {
+        println!("{}", result);
+    }
+}
+
+fn print_env(env: &Rc<RefCell<Environment>>) {
+    let env = env.borrow();
+    let mut bindings: Vec<(&str, &Object)> = env.bindings().collect();
+    bindings.sort_by_key(|(name, _)| *name);
+
+    for (name, value) in bindings {
+        println!("{} = {}", name, value);
+    }
+}
+
+fn load(path: &str, session: &mut Session, env: &Rc<RefCell<Environment>>) {
+    match std::fs::read_to_string(path) {
+        Ok(source) => print_eval(path, &source, session, env),
+        Err(err) => eprintln!("could not read {}: {}", path, err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_incomplete() {
+        assert!(!is_incomplete("let x = 5;", IntegerMode::Checked));
+        assert!(!is_incomplete("", IntegerMode::Checked));
+        assert!(is_incomplete("let add = fn(x, y) {", IntegerMode::Checked));
+        assert!(is_incomplete(
+            "let add = fn(x, y) {\n  x + y;",
+            IntegerMode::Checked
+        ));
+        assert!(!is_incomplete(
+            "let add = fn(x, y) {\n  x + y;\n};",
+            IntegerMode::Checked
+        ));
+        assert!(is_incomplete("add(1,", IntegerMode::Checked));
+        assert!(!is_incomplete("if (x) { 1 } }", IntegerMode::Checked));
+        assert!(is_incomplete("let xs = [1,", IntegerMode::Checked));
+
+        let big = "let f = fn() { 99999999999999999999 +";
+        assert!(is_incomplete(big, IntegerMode::BigInt));
+        assert!(!is_incomplete(big, IntegerMode::Checked));
+        assert!(!is_incomplete(
+            "let f = fn() { 99999999999999999999 }",
+            IntegerMode::BigInt
+        ));
+    }
+
+    /// Evaluates each `(name, text)` entry in one session and returns the rendered
+    /// error of the last
+    fn last_error(entries: &[(&str, &str)]) -> String {
+        let mut session = Session::default();
+        let env = Rc::new(RefCell::new(Environment::new()));
+        let mut result = Object::Null;
+
+        for (name, text) in entries {
+            let offset = session.push(name, text);
+            let mut parser = parser::Parser::new(lexer::Lexer::new_at(text, offset));
+            result = evaluator::eval_program(&parser.parse_program(), &env);
+        }
+        session.render(&result.to_diagnostic().expect("the last entry fails"))
+    }
+
+    #[test]
+    fn test_errors_render_against_their_entry() {
+        let err = last_error(&[
+            ("lib.mk", "let x = 1;\nlet bad = fn() {\n  x + true\n};"),
+            (SOURCE_NAME, "let y = 2;"),
+            (SOURCE_NAME, "bad()"),
+        ]);
+
+        assert!(err.contains("--> lib.mk:3:3\n"), "{}", err);
+        assert!(err.contains("3 |   x + true\n"), "{}", err);
+
+        let err = last_error(&[("lib.mk", "let x = 1;"), (SOURCE_NAME, "x + true")]);
+        assert!(err.contains("--> <repl>:1:1\n"), "{}", err);
+        assert!(err.contains("1 | x + true\n"), "{}", err);
+    }
 }
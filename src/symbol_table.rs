@@ -0,0 +1,292 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use crate::object::{Capture, FreeVariable};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SymbolScope {
+    Global,
+    /// A parameter or `let` of the function being compiled
+    Local,
+    Builtin,
+    /// A local of an enclosing function, indexing the free variables of the function
+    /// being compiled
+    Free,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Symbol {
+    pub name: String,
+    pub scope: SymbolScope,
+    pub index: usize,
+}
+
+/// The names visible while compiling one function, or the whole program for the
+/// outermost table.  Each function literal gets a table enclosed by the one it is
+/// written in
+#[derive(Debug, Default)]
+pub struct SymbolTable {
+    outer: Option<Box<SymbolTable>>,
+    store: HashMap<String, Symbol>,
+    /// Locals declared ahead of their `let`.  Until the `let` is compiled the body
+    /// itself reads the name from further out, as the evaluator would, while
+    /// enclosed functions already refer to the local
+    pending: HashSet<String>,
+    /// Kept apart from `store`, as the body can read an enclosing local before its
+    /// own `let` of the same name
+    free_symbols: HashMap<String, Symbol>,
+    /// The names of the globals or locals defined in this table, in slot order
+    pub definitions: Vec<String>,
+    /// The locals of enclosing functions this function refers to
+    pub free: Vec<FreeVariable>,
+    /// The slots of the locals that must be kept in cells: those an enclosed
+    /// function captures, and those first bound in an `if` block, which may not be
+    /// set when they are read
+    pub cells: BTreeSet<usize>,
+}
+
+impl SymbolTable {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    #[must_use]
+    pub fn new_enclosed(outer: Self) -> Self {
+        Self {
+            outer: Some(Box::new(outer)),
+            ..Self::default()
+        }
+    }
+
+    /// Gives back the enclosing table, or `None` for the outermost one
+    #[must_use]
+    pub fn into_outer(self) -> Option<Self> {
+        self.outer.map(|outer| *outer)
+    }
+
+    /// Defines `name` as a global in the outermost table, or a local otherwise.  A
+    /// name already defined in this table keeps its slot, so `let x = x + 1` updates
+    /// `x` in place like the evaluator does
+    pub fn define(&mut self, name: &str) -> Symbol {
+        let scope = if self.outer.is_some() {
+            SymbolScope::Local
+        } else {
+            SymbolScope::Global
+        };
+        self.pending.remove(name);
+
+        if let Some(symbol) = self.store.get(name).filter(|symbol| symbol.scope == scope) {
+            return symbol.clone();
+        }
+
+        let symbol = self.insert(name, scope, self.definitions.len());
+        self.definitions.push(name.to_owned());
+        symbol
+    }
+
+    /// Defines `name` for a `let` that has not been compiled yet.  `conditional` is
+    /// whether the `let` is in an `if` block
+    pub fn declare(&mut self, name: &str, conditional: bool) -> Symbol {
+        let defined = self.definitions.len();
+        let symbol = self.define(name);
+
+        // A name already bound here, such as a parameter, keeps its value until
+        // the `let` is compiled
+        if symbol.scope == SymbolScope::Local && symbol.index == defined {
+            self.pending.insert(name.to_owned());
+            if conditional {
+                self.cells.insert(symbol.index);
+            }
+        }
+        symbol
+    }
+
+    /// Defines `name` as a global however deeply this table is nested
+    pub fn define_global(&mut self, name: &str) -> Symbol {
+        match &mut self.outer {
+            Some(outer) => outer.define_global(name),
+            None => self.define(name),
+        }
+    }
+
+    pub fn define_builtin(&mut self, index: usize, name: &str) -> Symbol {
+        self.insert(name, SymbolScope::Builtin, index)
+    }
+
+    /// Looks `name` up here and then in the enclosing tables.  A local of an
+    /// enclosing function is recorded as a free variable of this one, and of every
+    /// function in between
+    pub fn resolve(&mut self, name: &str) -> Option<Symbol> {
+        self.resolve_from(name, true)
+    }
+
+    /// `own_body` is whether the name is read by this table's function itself,
+    /// rather than by one enclosed in it
+    fn resolve_from(&mut self, name: &str, own_body: bool) -> Option<Symbol> {
+        if let Some(symbol) = self.store.get(name) {
+            if !own_body || !self.pending.contains(name) {
+                return Some(symbol.clone());
+            }
+        }
+        if let Some(symbol) = self.free_symbols.get(name) {
+            return Some(symbol.clone());
+        }
+
+        let outer = self.outer.as_mut()?;
+        let symbol = outer.resolve_from(name, false)?;
+        let capture = match symbol.scope {
+            SymbolScope::Global | SymbolScope::Builtin => return Some(symbol),
+            SymbolScope::Local => {
+                outer.cells.insert(symbol.index);
+                Capture::Local(symbol.index)
+            }
+            SymbolScope::Free => Capture::Free(symbol.index),
+        };
+
+        self.free.push(FreeVariable {
+            name: name.to_owned(),
+            capture,
+        });
+        let symbol = Symbol {
+            name: name.to_owned(),
+            scope: SymbolScope::Free,
+            index: self.free.len() - 1,
+        };
+        self.free_symbols.insert(name.to_owned(), symbol.clone());
+        Some(symbol)
+    }
+
+    fn insert(&mut self, name: &str, scope: SymbolScope, index: usize) -> Symbol {
+        let symbol = Symbol {
+            name: name.to_owned(),
+            scope,
+            index,
+        };
+        self.store.insert(name.to_owned(), symbol.clone());
+        symbol
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn symbol(name: &str, scope: SymbolScope, index: usize) -> Symbol {
+        Symbol {
+            name: name.to_owned(),
+            scope,
+            index,
+        }
+    }
+
+    #[test]
+    fn test_define_and_resolve() {
+        let mut global = SymbolTable::new();
+        assert_eq!(symbol("a", SymbolScope::Global, 0), global.define("a"));
+        assert_eq!(symbol("b", SymbolScope::Global, 1), global.define("b"));
+        assert_eq!(symbol("a", SymbolScope::Global, 0), global.define("a"));
+        global.define_builtin(0, "len");
+
+        let mut local = SymbolTable::new_enclosed(global);
+        assert_eq!(symbol("c", SymbolScope::Local, 0), local.define("c"));
+
+        let mut nested = SymbolTable::new_enclosed(local);
+        assert_eq!(symbol("d", SymbolScope::Local, 0), nested.define("d"));
+
+        let mut innermost = SymbolTable::new_enclosed(nested);
+        assert_eq!(symbol("e", SymbolScope::Local, 0), innermost.define("e"));
+
+        let cases = [
+            ("a", symbol("a", SymbolScope::Global, 0)),
+            ("len", symbol("len", SymbolScope::Builtin, 0)),
+            ("c", symbol("c", SymbolScope::Free, 0)),
+            ("d", symbol("d", SymbolScope::Free, 1)),
+            ("e", symbol("e", SymbolScope::Local, 0)),
+            ("c", symbol("c", SymbolScope::Free, 0)),
+        ];
+        for (name, expected) in cases {
+            assert_eq!(Some(expected), innermost.resolve(name), "name: {}", name);
+        }
+        assert_eq!(None, innermost.resolve("f"));
+
+        let free = |name: &str, capture| FreeVariable {
+            name: name.to_owned(),
+            capture,
+        };
+        // `c` is two functions out, so the one in between captures it too
+        assert_eq!(
+            vec![free("c", Capture::Free(0)), free("d", Capture::Local(0))],
+            innermost.free
+        );
+        assert_eq!(vec!["e"], innermost.definitions);
+
+        let nested = innermost.into_outer().unwrap();
+        assert_eq!(vec![free("c", Capture::Local(0))], nested.free);
+        assert_eq!(BTreeSet::from([0]), nested.cells);
+        assert_eq!(BTreeSet::from([0]), nested.into_outer().unwrap().cells);
+    }
+
+    #[test]
+    fn test_declared_locals() {
+        let mut global = SymbolTable::new();
+        global.define("x");
+        let mut local = SymbolTable::new_enclosed(global);
+        local.define("param");
+        local.declare("x", false);
+        local.declare("y", true);
+        local.declare("param", true);
+
+        // Until its `let`, the function's own `x` is the global
+        assert_eq!(
+            Some(symbol("x", SymbolScope::Global, 0)),
+            local.resolve("x")
+        );
+        let mut nested = SymbolTable::new_enclosed(local);
+        assert_eq!(Some(symbol("x", SymbolScope::Free, 0)), nested.resolve("x"));
+
+        let mut local = nested.into_outer().unwrap();
+        assert_eq!(symbol("x", SymbolScope::Local, 1), local.define("x"));
+        assert_eq!(Some(symbol("x", SymbolScope::Local, 1)), local.resolve("x"));
+
+        // `y` may not be set, and `x` is captured, but a parameter is always set
+        assert_eq!(BTreeSet::from([1, 2]), local.cells);
+    }
+
+    #[test]
+    fn test_define_global_from_nested_table() {
+        let mut local = SymbolTable::new_enclosed(SymbolTable::new());
+        local.define("x");
+
+        assert_eq!(
+            symbol("later", SymbolScope::Global, 0),
+            local.define_global("later")
+        );
+        assert_eq!(
+            Some(symbol("later", SymbolScope::Global, 0)),
+            local.resolve("later")
+        );
+
+        let mut global = local.into_outer().unwrap();
+        assert_eq!(
+            symbol("later", SymbolScope::Global, 0),
+            global.define("later")
+        );
+    }
+
+    #[test]
+    fn test_shadowing() {
+        let mut global = SymbolTable::new();
+        global.define_builtin(0, "len");
+        assert_eq!(symbol("len", SymbolScope::Global, 0), global.define("len"));
+
+        let mut local = SymbolTable::new_enclosed(global);
+        local.define("x");
+        let mut nested = SymbolTable::new_enclosed(local);
+        assert_eq!(Some(symbol("x", SymbolScope::Free, 0)), nested.resolve("x"));
+        assert_eq!(symbol("x", SymbolScope::Local, 0), nested.define("x"));
+        assert_eq!(
+            Some(symbol("x", SymbolScope::Local, 0)),
+            nested.resolve("x")
+        );
+    }
+}
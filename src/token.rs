@@ -1,97 +1,200 @@
-#[derive(Debug, Default, PartialEq, Eq, Clone)]
-pub struct Token {
-    pub token_type: String,
-    pub literal: String,
+use core::fmt;
+
+/// Where a token sits in the source.  `start` and `end` are byte offsets into the
+/// input (`end` is exclusive), while `line` and `column` are 1-based and describe the
+/// first character of the token
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
 }
 
-impl Token {
+impl Span {
+    /// Returns a span that starts where `self` starts and ends where `other` ends
     #[must_use]
-    pub fn new(token_type: &str, literal: &str) -> Self {
+    pub const fn to(self, other: Self) -> Self {
         Self {
-            token_type: String::from(token_type),
-            literal: String::from(literal),
+            start: self.start,
+            end: other.end,
+            line: self.line,
+            column: self.column,
         }
     }
+}
 
-    fn lookup(literal: &str) -> Self {
-        match literal {
-            "fn" => Self::new(FUNCTION, "fn"),
-            "let" => Self::new(LET, "let"),
-            "true" => Self::new(TRUE, "true"),
-            "false" => Self::new(FALSE, "false"),
-            "if" => Self::new(IF, "if"),
-            "else" => Self::new(ELSE, "else"),
-            "return" => Self::new(RETURN, "return"),
-            "=" => Self::new(ASSIGN, "="),
-            ";" => Self::new(SEMICOLON, ";"),
-            "(" => Self::new(LPAREN, "("),
-            ")" => Self::new(RPAREN, ")"),
-            "," => Self::new(COMMA, ","),
-            "+" => Self::new(PLUS, "+"),
-            "{" => Self::new(LBRACE, "{"),
-            "}" => Self::new(RBRACE, "}"),
-            "!" => Self::new(BANG, "!"),
-            "-" => Self::new(MINUS, "-"),
-            "/" => Self::new(SLASH, "/"),
-            "*" => Self::new(ASTERISK, "*"),
-            "<" => Self::new(LT, "<"),
-            ">" => Self::new(GT, ">"),
-            "==" => Self::new(EQ, "=="),
-            "!=" => Self::new(NOT_EQ, "!="),
-            _ => Self::new(IDENT, literal),
-        }
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
     }
 }
 
-impl TryFrom<String> for Token {
-    type Error = anyhow::Error;
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenKind {
+    #[default]
+    Illegal,
+    Eof,
+
+    // Identifiers and literals
+    Ident,
+    Int,
+    Float,
+    String,
+
+    // Operators
+    Assign,
+    Plus,
+    Minus,
+    Bang,
+    Asterisk,
+    Slash,
+    Lt,
+    Gt,
+    Eq,
+    NotEq,
+
+    // Delimiters
+    Comma,
+    Semicolon,
+    Colon,
+
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    LBracket,
+    RBracket,
+
+    // Keywords
+    Function,
+    Let,
+    True,
+    False,
+    If,
+    Else,
+    Return,
+}
+
+impl TokenKind {
+    /// The source text of keywords and symbols, which is the same for every token of
+    /// that kind.  `None` for kinds whose text varies, such as identifiers
+    #[must_use]
+    pub const fn text(self) -> Option<&'static str> {
+        match self {
+            Self::Illegal | Self::Ident | Self::Int | Self::Float | Self::String => None,
+            Self::Eof => Some(""),
+            Self::Assign => Some("="),
+            Self::Plus => Some("+"),
+            Self::Minus => Some("-"),
+            Self::Bang => Some("!"),
+            Self::Asterisk => Some("*"),
+            Self::Slash => Some("/"),
+            Self::Lt => Some("<"),
+            Self::Gt => Some(">"),
+            Self::Eq => Some("=="),
+            Self::NotEq => Some("!="),
+            Self::Comma => Some(","),
+            Self::Semicolon => Some(";"),
+            Self::Colon => Some(":"),
+            Self::LParen => Some("("),
+            Self::RParen => Some(")"),
+            Self::LBrace => Some("{"),
+            Self::RBrace => Some("}"),
+            Self::LBracket => Some("["),
+            Self::RBracket => Some("]"),
+            Self::Function => Some("fn"),
+            Self::Let => Some("let"),
+            Self::True => Some("true"),
+            Self::False => Some("false"),
+            Self::If => Some("if"),
+            Self::Else => Some("else"),
+            Self::Return => Some("return"),
+        }
+    }
+
+    /// Classifies a word as a keyword, or `None` if it is an identifier
+    #[must_use]
+    pub fn keyword(word: &str) -> Option<Self> {
+        match word {
+            "fn" => Some(Self::Function),
+            "let" => Some(Self::Let),
+            "true" => Some(Self::True),
+            "false" => Some(Self::False),
+            "if" => Some(Self::If),
+            "else" => Some(Self::Else),
+            "return" => Some(Self::Return),
+            _ => None,
+        }
+    }
+}
 
-    fn try_from(input: String) -> Result<Self, anyhow::Error> {
-        Ok(Self::lookup(&input))
+impl fmt::Display for TokenKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Illegal => "ILLEGAL",
+            Self::Eof => "EOF",
+            Self::Ident => "IDENT",
+            Self::Int => "INT",
+            Self::Float => "FLOAT",
+            Self::String => "STRING",
+            Self::Function => "FUNCTION",
+            Self::Let => "LET",
+            Self::True => "TRUE",
+            Self::False => "FALSE",
+            Self::If => "IF",
+            Self::Else => "ELSE",
+            Self::Return => "RETURN",
+            symbol => symbol.text().unwrap_or_default(),
+        };
+        write!(f, "{}", name)
     }
 }
 
-impl TryFrom<i32> for Token {
-    type Error = anyhow::Error;
+#[derive(Debug, Default, Clone)]
+pub struct Token {
+    pub kind: TokenKind,
+    /// The source text of identifiers, integers and illegal characters, or the
+    /// contents of a string with its escapes resolved.  Left empty for keywords and
+    /// symbols, whose text is fixed by their kind
+    literal: String,
+    pub span: Span,
+}
+
+impl Token {
+    #[must_use]
+    pub fn new(kind: TokenKind, literal: &str) -> Self {
+        let literal = if kind.text().is_some() {
+            String::new()
+        } else {
+            String::from(literal)
+        };
+
+        Self {
+            kind,
+            literal,
+            span: Span::default(),
+        }
+    }
+
+    #[must_use]
+    pub const fn with_span(mut self, span: Span) -> Self {
+        self.span = span;
+        self
+    }
+
+    #[must_use]
+    pub fn literal(&self) -> &str {
+        self.kind.text().unwrap_or(&self.literal)
+    }
+}
 
-    fn try_from(input: i32) -> Result<Self, anyhow::Error> {
-        Ok(Self::new(INT, &input.to_string()))
+// The span only records where a token was found, so two tokens are equal when they
+// have the same kind and literal regardless of where they came from
+impl PartialEq for Token {
+    fn eq(&self, other: &Self) -> bool {
+        self.kind == other.kind && self.literal == other.literal
     }
 }
 
-pub const ILLEGAL: &str = "ILLEGAL";
-pub const EOF: &str = "EOF";
-
-// Identifiers and literals
-pub const IDENT: &str = "IDENT";
-pub const INT: &str = "INT";
-
-// Operators
-pub const ASSIGN: &str = "=";
-pub const PLUS: &str = "+";
-pub const MINUS: &str = "-";
-pub const BANG: &str = "!";
-pub const ASTERISK: &str = "*";
-pub const SLASH: &str = "/";
-pub const LT: &str = "<";
-pub const GT: &str = ">";
-pub const EQ: &str = "==";
-pub const NOT_EQ: &str = "!=";
-
-// Delimiters
-pub const COMMA: &str = ",";
-pub const SEMICOLON: &str = ";";
-
-pub const LPAREN: &str = "(";
-pub const RPAREN: &str = ")";
-pub const LBRACE: &str = "{";
-pub const RBRACE: &str = "}";
-
-// Keywords
-pub const FUNCTION: &str = "FUNCTION";
-pub const LET: &str = "LET";
-pub const TRUE: &str = "TRUE";
-pub const FALSE: &str = "FALSE";
-pub const IF: &str = "IF";
-pub const ELSE: &str = "ELSE";
-pub const RETURN: &str = "RETURN";
+impl Eq for Token {}
@@ -0,0 +1,666 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use crate::builtins;
+use crate::code::{self, Opcode};
+use crate::compiler::Bytecode;
+use crate::evaluator::{self, IntegerMode};
+use crate::interrupt::InterruptHandle;
+use crate::object::{Capture, Closure, CompiledFunction, LocalCell, Object};
+use crate::token::{Span, TokenKind};
+
+/// How deeply calls may nest before the program is stopped with an error
+const MAX_FRAMES: usize = evaluator::MAX_CALL_DEPTH;
+/// How many values the stack may hold across every frame
+const STACK_SIZE: usize = 1 << 20;
+
+/// A function call in progress
+#[derive(Debug)]
+struct Frame {
+    closure: Rc<Closure>,
+    /// The offset of the next instruction to run
+    ip: usize,
+    /// Where the function's locals start on the stack, followed by the values it
+    /// works on.  The function being called sits just below
+    base_pointer: usize,
+    /// The cells of the locals kept in them, by slot.  Empty when the function keeps
+    /// none, so most calls allocate nothing
+    cells: Vec<Option<LocalCell>>,
+}
+
+impl Frame {
+    fn function(&self) -> &CompiledFunction {
+        &self.closure.function
+    }
+}
+
+/// Runs compiled programs.  Operators, indexing and builtins share their
+/// implementation with the evaluator, so both give the same values and errors
+#[derive(Debug)]
+pub struct Vm {
+    constants: Vec<Object>,
+    /// `None` for a global that has not been set yet
+    globals: Vec<Option<Object>>,
+    global_names: Vec<String>,
+    stack: Vec<Object>,
+    /// The callers of the running function
+    frames: Vec<Frame>,
+    frame: Frame,
+    integer_mode: IntegerMode,
+    interrupt: InterruptHandle,
+}
+
+impl Vm {
+    #[must_use]
+    pub fn new(bytecode: Bytecode) -> Self {
+        let main = Closure {
+            function: Rc::new(bytecode.main),
+            free: Vec::new(),
+        };
+
+        Self {
+            constants: bytecode.constants,
+            globals: vec![None; bytecode.globals.len()],
+            global_names: bytecode.globals,
+            stack: Vec::new(),
+            frames: Vec::new(),
+            frame: Frame {
+                closure: Rc::new(main),
+                ip: 0,
+                base_pointer: 0,
+                cells: Vec::new(),
+            },
+            integer_mode: IntegerMode::default(),
+            interrupt: InterruptHandle::default(),
+        }
+    }
+
+    #[must_use]
+    pub const fn with_integer_mode(mut self, integer_mode: IntegerMode) -> Self {
+        self.integer_mode = integer_mode;
+        self
+    }
+
+    /// A handle that stops this machine's run in progress, checked on every call
+    #[must_use]
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
+    /// Runs the program to completion and returns its value, like `eval_program`.  A
+    /// runtime error stops the program and is returned as an `Object::Error`
+    pub fn run(&mut self) -> Object {
+        // As in the evaluator, an interrupt only applies to the run in progress
+        self.interrupt.take();
+        match self.execute() {
+            Ok(value) | Err(value) => value,
+        }
+    }
+
+    fn execute(&mut self) -> Result<Object, Object> {
+        loop {
+            let ip = self.frame.ip;
+            let instructions = &self.frame.function().instructions;
+
+            // Compiled functions always end in a return, so running off the end
+            // only happens with hand-written bytecode
+            let Some(&byte) = instructions.get(ip) else {
+                return Err(self.error("ran past the end of the instructions", ip));
+            };
+            let op = Opcode::try_from(byte)
+                .map_err(|byte| self.error(&format!("unknown opcode {}", byte), ip))?;
+            if ip + op.width() > instructions.len() {
+                return Err(self.error(&format!("{} is missing operands", op), ip));
+            }
+
+            let operands = &instructions[ip + 1..];
+            let first = match op.definition().operand_widths {
+                [2] => code::read_u16(operands),
+                [1] => usize::from(operands[0]),
+                _ => 0,
+            };
+            self.frame.ip += op.width();
+
+            match op {
+                Opcode::Constant => {
+                    let constant = self.constant(first, ip)?;
+                    self.push(constant);
+                }
+                Opcode::Pop => {
+                    self.pop();
+                }
+
+                Opcode::Add
+                | Opcode::Sub
+                | Opcode::Mul
+                | Opcode::Div
+                | Opcode::Equal
+                | Opcode::NotEqual
+                | Opcode::GreaterThan
+                | Opcode::LessThan => {
+                    let operator = match op {
+                        Opcode::Add => TokenKind::Plus,
+                        Opcode::Sub => TokenKind::Minus,
+                        Opcode::Mul => TokenKind::Asterisk,
+                        Opcode::Div => TokenKind::Slash,
+                        Opcode::Equal => TokenKind::Eq,
+                        Opcode::NotEqual => TokenKind::NotEq,
+                        Opcode::GreaterThan => TokenKind::Gt,
+                        _ => TokenKind::Lt,
+                    };
+                    let right = self.pop();
+                    let left = self.pop();
+                    let result = evaluator::eval_infix_expression(
+                        operator,
+                        left,
+                        right,
+                        self.integer_mode,
+                        Span::default(),
+                    );
+                    self.push_result(result, ip)?;
+                }
+                Opcode::Minus | Opcode::Bang => {
+                    let operator = if op == Opcode::Minus {
+                        TokenKind::Minus
+                    } else {
+                        TokenKind::Bang
+                    };
+                    let right = self.pop();
+                    let result = evaluator::eval_prefix_expression(
+                        operator,
+                        right,
+                        self.integer_mode,
+                        Span::default(),
+                    );
+                    self.push_result(result, ip)?;
+                }
+
+                Opcode::True => self.push(Object::Boolean(true)),
+                Opcode::False => self.push(Object::Boolean(false)),
+                Opcode::Null => self.push(Object::Null),
+
+                Opcode::JumpNotTruthy => {
+                    if !self.pop().is_truthy() {
+                        self.frame.ip = first;
+                    }
+                }
+                Opcode::Jump => self.frame.ip = first,
+
+                Opcode::GetGlobal => {
+                    let value = match self.globals.get(first) {
+                        Some(Some(value)) => value.clone(),
+                        // Like the evaluator, a builtin is used until a global of the
+                        // same name is set
+                        _ => {
+                            let name = self.global_names.get(first).map_or("?", String::as_str);
+                            builtins::lookup(name).ok_or_else(|| self.not_found(name, ip))?
+                        }
+                    };
+                    self.push(value);
+                }
+                Opcode::SetGlobal => {
+                    let value = self.pop();
+                    if first >= self.globals.len() {
+                        self.globals.resize(first + 1, None);
+                    }
+                    self.globals[first] = Some(value);
+                }
+                Opcode::GetLocal => {
+                    let value = self
+                        .stack
+                        .get(self.frame.base_pointer + first)
+                        .cloned()
+                        .ok_or_else(|| self.error(&format!("no local {}", first), ip))?;
+                    self.push(value);
+                }
+                Opcode::SetLocal => {
+                    let value = self.pop();
+                    match self.stack.get_mut(self.frame.base_pointer + first) {
+                        Some(local) => *local = value,
+                        None => return Err(self.error(&format!("no local {}", first), ip)),
+                    }
+                }
+                Opcode::GetCell => {
+                    let cell = self.cell(first, ip)?;
+                    let value = cell.borrow().clone();
+                    let name = || {
+                        self.frame
+                            .function()
+                            .locals
+                            .get(first)
+                            .map_or("?", String::as_str)
+                    };
+                    let value = value.ok_or_else(|| self.not_found(name(), ip))?;
+                    self.push(value);
+                }
+                Opcode::SetCell => {
+                    let value = self.pop();
+                    *self.cell(first, ip)?.borrow_mut() = Some(value);
+                }
+                Opcode::GetBuiltin => {
+                    let builtin = builtins::get(first)
+                        .ok_or_else(|| self.error(&format!("unknown builtin {}", first), ip))?;
+                    self.push(builtin);
+                }
+                Opcode::GetFree => {
+                    let closure = &self.frame.closure;
+                    let value = match (closure.free.get(first), closure.function.free.get(first)) {
+                        (Some(cell), Some(free)) => {
+                            let value = cell.borrow().clone();
+                            value.ok_or_else(|| self.not_found(&free.name, ip))?
+                        }
+                        _ => return Err(self.error(&format!("no free variable {}", first), ip)),
+                    };
+                    self.push(value);
+                }
+
+                Opcode::Array => {
+                    let elements = self.pop_many(first);
+                    self.push(Object::Array(elements));
+                }
+                Opcode::Hash => {
+                    let items = self.pop_many(first);
+                    let mut hash = BTreeMap::new();
+
+                    let mut items = items.into_iter();
+                    while let (Some(key), Some(value)) = (items.next(), items.next()) {
+                        let Some(key) = key.hash_key() else {
+                            let span = self.frame.function().span_at(ip);
+                            return Err(evaluator::unusable_hash_key(&key, span));
+                        };
+                        hash.insert(key, value);
+                    }
+
+                    self.push(Object::Hash(hash));
+                }
+                Opcode::Index => {
+                    let index = self.pop();
+                    let left = self.pop();
+                    let result = evaluator::eval_index_expression(left, index, Span::default());
+                    self.push_result(result, ip)?;
+                }
+
+                Opcode::Call => self.call(first, ip)?,
+                Opcode::ReturnValue => {
+                    let value = self.pop();
+                    if let Some(value) = self.return_from_frame(value) {
+                        return Ok(value);
+                    }
+                }
+                Opcode::Return => {
+                    if let Some(value) = self.return_from_frame(Object::Null) {
+                        return Ok(value);
+                    }
+                }
+                Opcode::Closure => {
+                    let Object::CompiledFunction(function) = self.constant(first, ip)? else {
+                        return Err(self.error("closure of a constant that is not a function", ip));
+                    };
+                    let free = function
+                        .free
+                        .iter()
+                        .map(|free| match free.capture {
+                            Capture::Local(slot) => self.cell(slot, ip),
+                            Capture::Free(index) => {
+                                self.frame.closure.free.get(index).cloned().ok_or_else(|| {
+                                    self.error(&format!("no free variable {}", index), ip)
+                                })
+                            }
+                        })
+                        .collect::<Result<_, _>>()?;
+                    self.push(Object::Closure(Rc::new(Closure { function, free })));
+                }
+            }
+        }
+    }
+
+    /// Calls the function below the `num_args` arguments on top of the stack
+    fn call(&mut self, num_args: usize, ip: usize) -> Result<(), Object> {
+        let span = self.frame.function().span_at(ip);
+        if self.interrupt.take() {
+            return Err(Object::error("interrupted".to_owned(), span));
+        }
+
+        // Only a file from `mkc::decode` can call with fewer values on the stack, as
+        // the instructions are checked but how they use the stack is not
+        let callee = self
+            .stack
+            .len()
+            .checked_sub(num_args + 1)
+            .map(|index| self.stack[index].clone())
+            .ok_or_else(|| self.error("stack underflow", ip))?;
+
+        match callee {
+            Object::Closure(closure) => {
+                let function = Rc::clone(&closure.function);
+                if function.parameters.len() != num_args {
+                    return Err(evaluator::wrong_number_of_arguments(
+                        function.parameters.len(),
+                        num_args,
+                        span,
+                    ));
+                }
+                if self.frames.len() >= MAX_FRAMES || self.stack.len() > STACK_SIZE {
+                    return Err(Object::error("stack overflow".to_owned(), span));
+                }
+
+                // The arguments are already in the slots of the parameters, so only the
+                // other locals need a place
+                let base_pointer = self.stack.len() - num_args;
+                self.stack.resize(
+                    base_pointer + function.locals.len().max(num_args),
+                    Object::Null,
+                );
+
+                let mut cells = Vec::new();
+                if !function.cells.is_empty() {
+                    cells.resize(function.locals.len(), None);
+                    for &slot in &function.cells {
+                        let value =
+                            (slot < num_args).then(|| self.stack[base_pointer + slot].clone());
+                        if let Some(cell) = cells.get_mut(slot) {
+                            *cell = Some(Rc::new(RefCell::new(value)));
+                        }
+                    }
+                }
+
+                let frame = Frame {
+                    closure,
+                    ip: 0,
+                    base_pointer,
+                    cells,
+                };
+                self.frames.push(std::mem::replace(&mut self.frame, frame));
+            }
+            Object::Builtin(builtin) => {
+                let args = self.pop_many(num_args);
+                self.pop();
+                let result = evaluator::apply_builtin(&builtin, &args, span);
+                self.push_result(result, ip)?;
+            }
+            other => {
+                return Err(Object::error(
+                    format!("not a function: {}", other.type_name()),
+                    span,
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Leaves the running function, replacing it on the stack with `value`.  Returns
+    /// the value back when leaving the program itself
+    fn return_from_frame(&mut self, value: Object) -> Option<Object> {
+        let Some(caller) = self.frames.pop() else {
+            return Some(value);
+        };
+        let frame = std::mem::replace(&mut self.frame, caller);
+        self.stack.truncate(frame.base_pointer - 1);
+        self.push(value);
+        None
+    }
+
+    fn constant(&self, index: usize, ip: usize) -> Result<Object, Object> {
+        self.constants
+            .get(index)
+            .cloned()
+            .ok_or_else(|| self.error(&format!("no constant at index {}", index), ip))
+    }
+
+    /// The cell of the local in `slot` of the running call
+    fn cell(&self, slot: usize, ip: usize) -> Result<LocalCell, Object> {
+        self.frame
+            .cells
+            .get(slot)
+            .cloned()
+            .flatten()
+            .ok_or_else(|| self.error(&format!("no cell for local {}", slot), ip))
+    }
+
+    /// The error for reading a variable that is not set, such as a global before its
+    /// `let` has run
+    fn not_found(&self, name: &str, ip: usize) -> Object {
+        self.error(&format!("identifier not found: {}", name), ip)
+    }
+
+    fn push(&mut self, value: Object) {
+        self.stack.push(value);
+    }
+
+    /// Pushes the result of an operation, or stops with it if it is an error.  The
+    /// shared operations do not know where they were called from, so the error is
+    /// given the span of the instruction at `ip`
+    fn push_result(&mut self, result: Object, ip: usize) -> Result<(), Object> {
+        match result {
+            Object::Error { message, .. } => Err(self.error(&message, ip)),
+            value => {
+                self.push(value);
+                Ok(())
+            }
+        }
+    }
+
+    fn pop(&mut self) -> Object {
+        self.stack.pop().unwrap_or(Object::Null)
+    }
+
+    fn pop_many(&mut self, count: usize) -> Vec<Object> {
+        self.stack.split_off(self.stack.len().saturating_sub(count))
+    }
+
+    fn error(&self, message: &str, ip: usize) -> Object {
+        Object::error(message.to_owned(), self.frame.function().span_at(ip))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compiler;
+    use crate::environment::Environment;
+    use crate::lexer;
+    use crate::parser;
+
+    fn parse(input: &str) -> crate::ast::Program {
+        let lexer = lexer::Lexer::new(input);
+        let mut parser = parser::Parser::new(lexer);
+        let program = parser.parse_program();
+        assert!(parser.errors.is_empty(), "{:?}", parser.errors);
+        program
+    }
+
+    fn test_run(input: &str) -> Object {
+        let bytecode = compiler::compile(&parse(input)).unwrap();
+        Vm::new(bytecode).run()
+    }
+
+    #[test]
+    fn test_integer_arithmetic() {
+        let cases = [
+            ("1", 1),
+            ("1 + 2", 3),
+            ("50 / 2 * 2 + 10 - 5", 55),
+            ("-50 + 100 + -50", 0),
+            ("(5 + 10 * 2 + 15 / 3) * 2 + -10", 50),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(
+                Object::Integer(expected),
+                test_run(input),
+                "input: {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_conditionals() {
+        let cases = [
+            ("if (true) { 10 }", Object::Integer(10)),
+            ("if (1 > 2) { 10 } else { 20 }", Object::Integer(20)),
+            ("if (false) { 10 }", Object::Null),
+            (
+                "if ((if (false) { 10 })) { 10 } else { 20 }",
+                Object::Integer(20),
+            ),
+            ("if (true) { let x = 1; }", Object::Null),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(expected, test_run(input), "input: {}", input);
+        }
+    }
+
+    #[test]
+    fn test_functions_and_closures() {
+        let cases = [
+            ("let f = fn() { 5 + 10 }; f()", 15),
+            ("let f = fn() { return 99; 100 }; f()", 99),
+            ("let sum = fn(a, b) { let c = a + b; c }; sum(1, 2) + sum(3, 4)", 10),
+            (
+                "let adder = fn(a) { fn(b) { fn(c) { a + b + c } } }; adder(1)(2)(3)",
+                6,
+            ),
+            (
+                "let fib = fn(n) { if (n < 2) { n } else { fib(n - 1) + fib(n - 2) } }; fib(15)",
+                610,
+            ),
+            (
+                "let wrapper = fn() { let count = fn(x) { if (x == 0) { 0 } else { count(x - 1) } }; count(3) }; wrapper()",
+                0,
+            ),
+            ("let even = fn(n) { if (n == 0) { 1 } else { odd(n - 1) } }; let odd = fn(n) { if (n == 0) { 0 } else { even(n - 1) } }; even(10)", 1),
+        ];
+
+        for (input, expected) in cases {
+            assert_eq!(
+                Object::Integer(expected),
+                test_run(input),
+                "input: {}",
+                input
+            );
+        }
+    }
+
+    #[test]
+    fn test_runtime_errors() {
+        let cases = [
+            ("5 + true;", "type mismatch: INTEGER + BOOLEAN", (1, 1)),
+            (
+                "let f = fn(x) {\n  -x\n}; f(true)",
+                "unknown operator: -BOOLEAN",
+                (2, 3),
+            ),
+            (
+                "let f = fn(a) { a }; f()",
+                "wrong number of arguments: expected 1, got 0",
+                (1, 22),
+            ),
+            ("missing + 1", "identifier not found: missing", (1, 1)),
+            ("1(2)", "not a function: INTEGER", (1, 1)),
+            ("{[1]: 2}", "unusable as hash key: ARRAY", (1, 1)),
+            ("let f = fn() { f() }; f()", "stack overflow", (1, 16)),
+            // Names are resolved when compiled, so `x` is the function's own local
+            // throughout its body, before its `let` as well as after
+            (
+                "let x = 1; (fn() { let g = fn() { x }; let a = g(); let x = 2; [a, g()] })()",
+                "identifier not found: x",
+                (1, 35),
+            ),
+        ];
+
+        for (input, message, (line, column)) in cases {
+            match test_run(input) {
+                Object::Error { message: got, span } => {
+                    assert_eq!(message, got, "input: {}", input);
+                    assert_eq!((line, column), (span.line, span.column), "input: {}", input);
+                }
+                other => panic!("expected an error for {}, got {:?}", input, other),
+            }
+        }
+    }
+
+    #[test]
+    fn test_bigint_mode() {
+        let program = parse("let f = fn(n) { if (n < 2) { 1 } else { n * f(n - 1) } }; -f(21)");
+        let bytecode = compiler::compile(&program).unwrap();
+
+        match Vm::new(bytecode.clone()).run() {
+            Object::Error { message, .. } => {
+                assert_eq!("integer overflow: 21 * 2432902008176640000", message);
+            }
+            other => panic!("expected an overflow, got {:?}", other),
+        }
+
+        let result = Vm::new(bytecode)
+            .with_integer_mode(IntegerMode::BigInt)
+            .run();
+        assert_eq!("-51090942171709440000", result.to_string());
+    }
+
+    /// Every program gives the same value, or the same error message, in both
+    /// the evaluator and the virtual machine
+    #[test]
+    fn test_matches_evaluator() {
+        let inputs = [
+            "1; let x = 2;",
+            "\"foo\" + \"bar\"",
+            "\"a\" == \"a\"",
+            "!5",
+            "!!true",
+            "1 < 2 == true",
+            "10 / 0",
+            "[1, 2 * 2, 3 + 3][1]",
+            "[1, 2, 3][3]",
+            "{\"one\": 1, true: 2, 3: 3}",
+            "{\"one\": 1}[\"two\"]",
+            "{1: 2}[fn(x) { x }]",
+            "len(\"hello\") + len([1, 2])",
+            "let a = [1, 2, 3]; push(rest(a), last(a))",
+            "len(1)",
+            "first([])",
+            "let map = fn(arr, f) { if (len(arr) == 0) { [] } else { push(map(rest(arr), f), f(first(arr))) } }; map([1, 2, 3], fn(x) { x * 2 })",
+            "let x = 1; let x = x + 1; x",
+            "let f = fn(x) { let x = x * 2; x }; f(4)",
+            "let len = fn(x) { 42 }; len([])",
+            "if (true) { if (true) { return 10; } return 1; }",
+            "fn(x) { x }",
+            "len",
+            "fn() { }()",
+            "let f = fn() { let g = fn() { h() }; g() }; let h = fn() { 7 }; f()",
+            "puts(1)",
+            "1 + 2.5 * 2",
+            "7 / 2.0 == 3.5",
+            "-0.5 < 0",
+            "{1.5: 1}",
+            "1.5 + true",
+            "let f = fn() { f() }; f()",
+            "(fn() { let a = fn() { b() }; let b = fn() { 1 }; a() })()",
+            "(fn() { let f = fn(x) { len(x) }; let len = fn(x) { 42 }; f([1]) })()",
+            "(fn() { let f = fn() { f }; let g = f; let f = 5; g() })()",
+            "let f = fn() { f }; let g = f; let f = 5; g()",
+            "let f = fn(x) { len(x) }; let r = f([1]); let len = fn(x) { 42 }; [r, f([1])]",
+            "(fn() { let a = fn() { b }; a() })(); let b = 1;",
+            "(fn() { let n = 0; let inc = fn() { let n = n + 1; n }; [inc(), inc(), n] })()",
+            "(fn(x) { if (x) { let y = 1; } fn() { y } })(true)()",
+            "(fn(x) { if (x) { let y = 1; } fn() { y } })(false)()",
+        ];
+
+        for input in inputs {
+            let program = parse(input);
+            let env = Rc::new(RefCell::new(Environment::new()));
+            let expected = crate::evaluator::eval_program(&program, &env);
+            let got = Vm::new(compiler::compile(&program).unwrap()).run();
+
+            match (&expected, &got) {
+                (Object::Error { message: a, .. }, Object::Error { message: b, .. }) => {
+                    assert_eq!(a, b, "input: {}", input);
+                }
+                _ => assert_eq!(expected.to_string(), got.to_string(), "input: {}", input),
+            }
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+fn monkey(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_monkey"))
+        .args(args)
+        .output()
+        .expect("failed to run monkey")
+}
+
+/// Writes `source` to a file of its own in the temporary directory
+fn script(name: &str, source: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("monkey-cli-{}-{}", std::process::id(), name));
+    std::fs::write(&path, source).unwrap();
+    path
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn test_eval() {
+    let output = monkey(&["-e", "let x = 2; x * 21"]);
+
+    assert_eq!(Some(0), output.status.code(), "{}", stderr(&output));
+    assert_eq!("42\n", String::from_utf8_lossy(&output.stdout));
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn test_usage_error() {
+    let output = monkey(&["--no-such-option"]);
+
+    assert_eq!(Some(64), output.status.code());
+    assert!(
+        stderr(&output).starts_with("Usage: monkey"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn test_parse_error() {
+    let path = script("parse.mk", "let x = 1;\nlet = 2;\n");
+    let output = monkey(&[path.to_str().unwrap()]);
+    std::fs::remove_file(&path).unwrap();
+
+    let err = stderr(&output);
+    assert_eq!(Some(65), output.status.code(), "{}", err);
+    assert!(
+        err.starts_with("error[E0001]: expected next token to be IDENT, got = instead\n"),
+        "{}",
+        err
+    );
+    assert!(
+        err.contains(&format!("--> {}:2:5\n", path.display())),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_missing_file() {
+    let path = std::env::temp_dir().join("monkey-cli-no-such-file.mk");
+    let output = monkey(&[path.to_str().unwrap()]);
+
+    let err = stderr(&output);
+    assert_eq!(Some(66), output.status.code(), "{}", err);
+    assert!(
+        err.starts_with(&format!("error: could not read {}: ", path.display())),
+        "{}",
+        err
+    );
+}
+
+#[test]
+fn test_runtime_error() {
+    let path = script(
+        "runtime.mk",
+        "puts(\"before\");\n1 + true;\nputs(\"after\");\n",
+    );
+    let output = monkey(&[path.to_str().unwrap()]);
+    std::fs::remove_file(&path).unwrap();
+
+    let err = stderr(&output);
+    assert_eq!(Some(70), output.status.code(), "{}", err);
+    assert_eq!("before\n", String::from_utf8_lossy(&output.stdout));
+    assert!(
+        err.starts_with("error[E0100]: type mismatch: INTEGER + BOOLEAN\n"),
+        "{}",
+        err
+    );
+    assert!(
+        err.contains(&format!("--> {}:2:1\n", path.display())),
+        "{}",
+        err
+    );
+}